@@ -1,9 +1,9 @@
 extern crate swrender;
 
-use swrender::renderer::{Camera, Context, draw_mesh_textured};
+use swrender::renderer::{AntiAliasing, Attenuation, Camera, Context, Light, Material, SampleCount, ShadingMode, Texture, draw_mesh_textured};
 use swrender::math::{Point3f, Vec3f};
 use swrender::obj;
-use swrender::tgaimage::{tga_format, TGAImage};
+use swrender::tgaimage::{tga_format, TGAColor, TGAImage};
 
 #[allow(unused_variables)]
 fn main() {
@@ -13,16 +13,28 @@ fn main() {
     let texture = TGAImage::from_tga_file("assets/african_head_diffuse.tga").unwrap();
     let light_dir = Vec3f { x: -3.0, y: -1.0, z: -3.0 }.normalize();
 
-    let camera_xp_yp_zp = Camera::new(
-        Point3f { x: 1.0, y: 0.2, z: 1.0 },
+    let camera_xp_yp_zp = Camera::perspective(
+        Point3f { x: 2.5, y: 0.6, z: 2.5 },
         Vec3f { x: -1.0, y: -0.3, z: -1.0 },
         Vec3f { x: 0.0, y: 1.0, z: 0.0 },
-        0.75
+        35.0f32.to_radians(),
+        1.0,
+        0.1,
+        10.0
     );
-    let ctx = Context {
-        camera: camera_xp_yp_zp,
-        light: light_dir,
-    };
+    let mut ctx = Context::new(camera_xp_yp_zp, light_dir);
+    ctx.shading = ShadingMode::Phong;
+    ctx.anti_aliasing = AntiAliasing::Msaa(SampleCount::X4);
+
+    ctx.lights[0] = Light::directional_with_shadow(light_dir, &model, IMAGE_SIZE);
+    let shadow = ctx.lights[0].shadow().unwrap();
+    shadow.depth_image().write_to_file("assets/mesh_head_shadow_depth.tga").unwrap();
+    // dim bluish fill light from the other side
+    let mut fill = Light::point(Point3f { x: 2.0, y: 1.0, z: -1.0 });
+    fill.color = TGAColor::from_rgb(120, 150, 255);
+    fill.intensity = 2.0;
+    fill.attenuation = Attenuation::INVERSE_SQUARE;
+    ctx.lights.push(fill);
 
     //tmp
     /*
@@ -45,9 +57,11 @@ fn main() {
     */
     texture.write_to_file("assets/turbo.tga").unwrap();
 
-    draw_mesh_textured(&model, &ctx, &mut image, &texture);
+    let diffuse = Texture::new(texture);
+    let material = Material::new(&diffuse);
+    draw_mesh_textured(&model, &ctx, &mut image, &material);
 
-    image.flip_vertically().unwrap();
     image.write_to_file("assets/mesh_head.tga").unwrap();
+    image.write_png_file("assets/mesh_head.png").unwrap();
 }
 
@@ -1,6 +1,7 @@
 extern crate swrender;
 
-use swrender::renderer::draw_line;
+use swrender::math::Point2f;
+use swrender::renderer::{draw_line, draw_line_aa, draw_line_styled, LineCap, LineStyle};
 use swrender::tgaimage::{tga_format, TGAColor, TGAImage};
 
 fn main() {
@@ -14,7 +15,17 @@ fn main() {
     draw_line(20, 13, 40, 80, &mut image, red);
     draw_line(80, 40, 13, 20, &mut image, white);
 
-    image.flip_vertically().unwrap();
     image.write_to_file("assets/threelines.tga").unwrap();
+
+    let mut styled = TGAImage::with_size(100, 100, tga_format::RGB);
+    let pnt = |x, y| Point2f { x, y };
+    draw_line_aa(pnt(13.0, 20.0), pnt(80.0, 40.0), &mut styled, red);
+    draw_line_aa(pnt(20.0, 13.0), pnt(40.0, 80.0), &mut styled, white);
+    let thick = LineStyle { width: 6.0, cap: LineCap::Round, anti_aliased: true, ..LineStyle::default() };
+    draw_line_styled(pnt(55.0, 60.0), pnt(90.0, 85.0), &thick, &mut styled, white);
+    let dashed = LineStyle { width: 2.0, dashes: vec![6.0, 3.0], anti_aliased: true, ..LineStyle::default() };
+    draw_line_styled(pnt(10.0, 92.0), pnt(90.0, 10.0), &dashed, &mut styled, red);
+
+    styled.write_to_file("assets/styled_lines.tga").unwrap();
 }
 
@@ -26,7 +26,6 @@ fn main() {
     let v3_3 = Point2i { x: 160, y: 45 };
     draw_triangle(v3_1, v3_2, v3_3, &mut image, red);
 
-    image.flip_vertically().unwrap();
     image.write_to_file("assets/threetriangles-parallel.tga").unwrap();
 }
 
@@ -1,6 +1,6 @@
 extern crate swrender;
 
-use swrender::renderer::{Camera, draw_mesh_wireframe};
+use swrender::renderer::{Camera, WireframeEdges, WireframeOptions, draw_mesh_wireframe, draw_mesh_wireframe_with};
 use swrender::math::{Point3f, Vec3};
 use swrender::obj;
 use swrender::tgaimage::{tga_format, TGAColor, TGAImage};
@@ -13,15 +13,31 @@ fn main() {
     let mut image = TGAImage::with_size(IMAGE_SIZE, IMAGE_SIZE, tga_format::RGB);
     let model = obj::load_obj_file("assets/african_head.obj").unwrap();
 
-    let camera_xp_yp_zp = Camera::new(
-        Point3f { x: 1.0, y: 0.3, z: 1.0 },
+    let camera_xp_yp_zp = Camera::perspective(
+        Point3f { x: 2.5, y: 0.75, z: 2.5 },
         Vec3 { x: -1.0, y: -0.3, z: -1.0 },
         Vec3 { x: 0.0, y: 1.0, z: 0.0 },
-        0.9
+        35.0f32.to_radians(),
+        1.0,
+        0.1,
+        10.0
     );
 
     draw_mesh_wireframe(&model, &camera_xp_yp_zp, &mut image, white);
 
-    image.flip_vertically().unwrap();
     image.write_to_file("assets/wireframe.tga").unwrap();
+
+    let mut hidden = TGAImage::with_size(IMAGE_SIZE, IMAGE_SIZE, tga_format::RGB);
+    let options = WireframeOptions {
+        hidden_lines: true,
+        edges: WireframeEdges::Feature { crease_angle: 40.0f32.to_radians() },
+        ..WireframeOptions::default()
+    };
+    draw_mesh_wireframe_with(&model, &camera_xp_yp_zp, &options, &mut hidden, white);
+    hidden.write_to_file("assets/wireframe_hidden.tga").unwrap();
+
+    let mut overlay = TGAImage::with_size(IMAGE_SIZE, IMAGE_SIZE, tga_format::RGB);
+    let options = WireframeOptions { fill: Some(TGAColor::from_rgb(70, 90, 120)), ..WireframeOptions::default() };
+    draw_mesh_wireframe_with(&model, &camera_xp_yp_zp, &options, &mut overlay, white);
+    overlay.write_to_file("assets/wireframe_overlay.tga").unwrap();
 }
@@ -0,0 +1,181 @@
+use crate::tgaimage::{tga_format, TGAColor, TGAImage};
+
+/// Render target the rasterizer draws into.
+///
+/// Coordinates are in pixels with (0, 0) at the first pixel in memory, which
+/// the renderer treats as the bottom left corner of the picture.
+pub trait Framebuffer {
+    fn width(&self) -> i32;
+    fn height(&self) -> i32;
+
+    /// Color of the pixel, `None` if the coordinates are outside the buffer.
+    fn get_pixel(&self, x: i32, y: i32) -> Option<TGAColor>;
+
+    /// Overwrite the pixel color, returns `false` and does nothing if the
+    /// coordinates are outside the buffer.
+    fn set_pixel(&mut self, x: i32, y: i32, color: TGAColor) -> bool;
+}
+
+impl Framebuffer for TGAImage {
+    fn width(&self) -> i32 {
+        self.width
+    }
+
+    fn height(&self) -> i32 {
+        self.height
+    }
+
+    fn get_pixel(&self, x: i32, y: i32) -> Option<TGAColor> {
+        self.get(x, y).ok()
+    }
+
+    fn set_pixel(&mut self, x: i32, y: i32, color: TGAColor) -> bool {
+        self.set(x, y, color).is_ok()
+    }
+}
+
+/// Framebuffer that discards all colors, for passes that only produce depth.
+#[derive(Clone, Copy, Debug)]
+pub struct NullFramebuffer {
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Framebuffer for NullFramebuffer {
+    fn width(&self) -> i32 {
+        self.width
+    }
+
+    fn height(&self) -> i32 {
+        self.height
+    }
+
+    fn get_pixel(&self, _x: i32, _y: i32) -> Option<TGAColor> {
+        None
+    }
+
+    fn set_pixel(&mut self, x: i32, y: i32, _color: TGAColor) -> bool {
+        x >= 0 && y >= 0 && x < self.width && y < self.height
+    }
+}
+
+/// In-memory RGBA framebuffer with floating point components in [0, 1].
+#[derive(Clone, Debug, PartialEq)]
+pub struct FloatImage {
+    data: Vec<[f32; 4]>,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl FloatImage {
+    pub fn with_size(w: i32, h: i32) -> Self {
+        FloatImage {
+            data: vec![[0.0, 0.0, 0.0, 1.0]; (w * h) as usize],
+            width: w,
+            height: h,
+        }
+    }
+
+    fn offset(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            None
+        } else {
+            Some((x + y * self.width) as usize)
+        }
+    }
+
+    /// Components of the pixel in RGBA order.
+    pub fn get_rgba(&self, x: i32, y: i32) -> Option<[f32; 4]> {
+        self.offset(x, y).map(|offset| self.data[offset])
+    }
+
+    pub fn set_rgba(&mut self, x: i32, y: i32, rgba: [f32; 4]) -> bool {
+        if let Some(offset) = self.offset(x, y) {
+            self.data[offset] = rgba;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Raw pixel data in rows of RGBA quadruples.
+    pub fn data(&self) -> &[[f32; 4]] {
+        self.data.as_slice()
+    }
+
+    pub fn clear(&mut self) {
+        self.data.fill([0.0, 0.0, 0.0, 1.0]);
+    }
+
+    pub fn to_tga(&self) -> TGAImage {
+        let mut image = TGAImage::with_size(self.width, self.height, tga_format::RGBA);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                image.set(x, y, self.get_pixel(x, y).unwrap()).unwrap();
+            }
+        }
+        image
+    }
+}
+
+fn to_unorm(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+impl Framebuffer for FloatImage {
+    fn width(&self) -> i32 {
+        self.width
+    }
+
+    fn height(&self) -> i32 {
+        self.height
+    }
+
+    fn get_pixel(&self, x: i32, y: i32) -> Option<TGAColor> {
+        self.get_rgba(x, y).map(|[r, g, b, a]| {
+            TGAColor::from_rgba(to_unorm(r), to_unorm(g), to_unorm(b), to_unorm(a))
+        })
+    }
+
+    fn set_pixel(&mut self, x: i32, y: i32, color: TGAColor) -> bool {
+        let a = if color.bytespp() == tga_format::RGBA { color.a() as f32 / 255.0 } else { 1.0 };
+        // grayscale colors keep their value in the first (blue) component
+        let (r, g, b) = if color.bytespp() == tga_format::GRAYSCALE {
+            (color.b(), color.b(), color.b())
+        } else {
+            (color.r(), color.g(), color.b())
+        };
+        let rgba = [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, a];
+        self.set_rgba(x, y, rgba)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FloatImage, Framebuffer};
+    use crate::tgaimage::{tga_format, TGAColor};
+
+    #[test]
+    fn float_image_round_trip() {
+        let mut image = FloatImage::with_size(4, 3);
+        let color = TGAColor::from_rgba(10, 128, 255, 64);
+        assert!(image.set_pixel(3, 2, color));
+        assert_eq!(image.get_pixel(3, 2), Some(color));
+        assert!(!image.set_pixel(4, 2, color));
+        assert_eq!(image.get_pixel(0, 3), None);
+
+        let opaque = image.get_rgba(1, 1).unwrap();
+        assert_eq!(opaque, [0.0, 0.0, 0.0, 1.0]);
+        image.set_pixel(1, 1, TGAColor::from_rgb(255, 0, 0));
+        assert_eq!(image.get_rgba(1, 1).unwrap(), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(image.to_tga().get(3, 2).unwrap(), color);
+    }
+
+    #[test]
+    fn float_image_grayscale_colors() {
+        let mut image = FloatImage::with_size(2, 2);
+        let gray = TGAColor::from_component_slice(&[51], tga_format::GRAYSCALE);
+        assert!(image.set_pixel(0, 1, gray));
+        assert_eq!(image.get_rgba(0, 1).unwrap(), [0.2, 0.2, 0.2, 1.0]);
+    }
+}
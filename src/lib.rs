@@ -1,6 +1,7 @@
 pub mod mesh;
 pub mod math;
 pub mod tgaimage;
+pub mod framebuffer;
 pub mod obj;
 pub mod renderer;
 
@@ -278,7 +278,7 @@ impl Mul<Vec3<i32>> for i32 {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct BndBox2<S> {
     pub min: Point2<S>,
     pub max: Point2<S>,
@@ -343,6 +343,7 @@ impl<S> BndBox2<S> where
     }
 }
 
+#[derive(Clone, Copy, Debug)]
 pub struct BndBox3<S> {
     pub min: Point3<S>,
     pub max: Point3<S>,
@@ -382,10 +383,24 @@ impl<S> BndBox3<S> where
     }
 
     pub fn center(&self) -> Point3<S> {
-        let three = <S as From<_>>::from(3);
-        Point3 { x: (self.min.x + self.max.x) / three,
-                 y: (self.min.y + self.max.y) / three,
-                 z: (self.min.z + self.max.z) / three }
+        let two = <S as From<_>>::from(2);
+        Point3 { x: (self.min.x + self.max.x) / two,
+                 y: (self.min.y + self.max.y) / two,
+                 z: (self.min.z + self.max.z) / two }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::{BndBox3f, Point3f};
+
+    #[test]
+    fn bounding_box_center() {
+        let mut bbox = BndBox3f::new_empty();
+        bbox.add_point(Point3f { x: 0.0, y: 0.0, z: 0.0 });
+        bbox.add_point(Point3f { x: 2.0, y: 4.0, z: -6.0 });
+        let center = bbox.center();
+        assert_eq!((center.x, center.y, center.z), (1.0, 2.0, -3.0));
+    }
+}
@@ -1,3 +1,7 @@
+use std::ops::{Add, Index, IndexMut, Mul, Sub};
+use super::{Point3, Vec3, EPSILON};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct HVec4<S> {
     pub x: S,
     pub y: S,
@@ -5,13 +9,79 @@ pub struct HVec4<S> {
     pub w: S,
 }
 
-impl<S> HVec4<S> {
+pub type HVec4f = HVec4<f32>;
+
+impl<S> HVec4<S> where
+    S: Mul<Output = S> +
+       Add<Output = S> +
+       Copy {
+
+    pub fn dot(&self, other: Self) -> S {
+        self.x * other.x + self.y * other.y + self.z * other.z + self.w * other.w
+    }
+}
+
+impl HVec4<f32> {
+    /// Drop the homogeneous coordinate without the perspective division.
+    pub fn to_vec3(&self) -> Vec3<f32> {
+        Vec3 { x: self.x, y: self.y, z: self.z }
+    }
+
+    /// Perspective division of the homogeneous point.
+    pub fn to_point3(&self) -> Point3<f32> {
+        let w_recip = self.w.recip();
+        Point3 { x: self.x * w_recip, y: self.y * w_recip, z: self.z * w_recip }
+    }
+
+    pub fn lerp(&self, other: Self, t: f32) -> Self {
+        *self + (other - *self) * t
+    }
+}
+
+impl<S> From<Point3<S>> for HVec4<S> where S: From<i16> {
+    fn from(p: Point3<S>) -> Self {
+        Self { x: p.x, y: p.y, z: p.z, w: 1.into() }
+    }
+}
+
+impl<S> From<Vec3<S>> for HVec4<S> where S: From<i16> {
+    fn from(v: Vec3<S>) -> Self {
+        Self { x: v.x, y: v.y, z: v.z, w: 0.into() }
+    }
+}
+
+impl<S> Add for HVec4<S> where S: Add<Output = S> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self { x: self.x + rhs.x, y: self.y + rhs.y, z: self.z + rhs.z, w: self.w + rhs.w }
+    }
+}
+
+impl<S> Sub for HVec4<S> where S: Sub<Output = S> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self { x: self.x - rhs.x, y: self.y - rhs.y, z: self.z - rhs.z, w: self.w - rhs.w }
+    }
+}
+
+impl<S> Mul<S> for HVec4<S> where S: Mul<Output = S> + Copy {
+    type Output = Self;
+
+    fn mul(self, rhs: S) -> Self::Output {
+        Self { x: self.x * rhs, y: self.y * rhs, z: self.z * rhs, w: self.w * rhs }
+    }
 }
 
+/// 4x4 matrix stored in row-major order, applied to column vectors.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct HMat4<S> {
     arr: [S; 16],
 }
 
+pub type HMat4f = HMat4<f32>;
+
 impl<S> HMat4<S> where S: From<i16> {
     pub fn eye() -> Self{
         Self {
@@ -23,3 +93,275 @@ impl<S> HMat4<S> where S: From<i16> {
     }
 }
 
+impl<S> HMat4<S> where S: Copy {
+    pub fn from_rows(rows: [[S; 4]; 4]) -> Self {
+        let mut arr = [rows[0][0]; 16];
+        for (i, row) in rows.iter().enumerate() {
+            arr[i * 4..(i + 1) * 4].copy_from_slice(row);
+        }
+        Self { arr }
+    }
+
+    pub fn row(&self, i: usize) -> HVec4<S> {
+        HVec4 { x: self[(i, 0)], y: self[(i, 1)], z: self[(i, 2)], w: self[(i, 3)] }
+    }
+
+    pub fn col(&self, j: usize) -> HVec4<S> {
+        HVec4 { x: self[(0, j)], y: self[(1, j)], z: self[(2, j)], w: self[(3, j)] }
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut arr = self.arr;
+        for i in 0..4 {
+            for j in 0..4 {
+                arr[i * 4 + j] = self.arr[j * 4 + i];
+            }
+        }
+        Self { arr }
+    }
+}
+
+impl<S> Index<(usize, usize)> for HMat4<S> {
+    type Output = S;
+
+    fn index(&self, (row, col): (usize, usize)) -> &Self::Output {
+        &self.arr[row * 4 + col]
+    }
+}
+
+impl<S> IndexMut<(usize, usize)> for HMat4<S> {
+    fn index_mut(&mut self, (row, col): (usize, usize)) -> &mut Self::Output {
+        &mut self.arr[row * 4 + col]
+    }
+}
+
+impl<S> Mul for HMat4<S> where
+    S: Mul<Output = S> +
+       Add<Output = S> +
+       Copy {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        let mut arr = self.arr;
+        for i in 0..4 {
+            for j in 0..4 {
+                arr[i * 4 + j] = self.row(i).dot(rhs.col(j));
+            }
+        }
+        Self { arr }
+    }
+}
+
+impl<S> Mul<HVec4<S>> for HMat4<S> where
+    S: Mul<Output = S> +
+       Add<Output = S> +
+       Copy {
+    type Output = HVec4<S>;
+
+    fn mul(self, rhs: HVec4<S>) -> Self::Output {
+        HVec4 {
+            x: self.row(0).dot(rhs),
+            y: self.row(1).dot(rhs),
+            z: self.row(2).dot(rhs),
+            w: self.row(3).dot(rhs),
+        }
+    }
+}
+
+impl HMat4<f32> {
+    pub fn translation(offset: Vec3<f32>) -> Self {
+        Self::from_rows([[1.0, 0.0, 0.0, offset.x],
+                         [0.0, 1.0, 0.0, offset.y],
+                         [0.0, 0.0, 1.0, offset.z],
+                         [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    /// Rotation by `angle` radians about `axis` passing through the origin,
+    /// counter-clockwise when looking against the axis direction.
+    pub fn rotation(axis: Vec3<f32>, angle: f32) -> Self {
+        let Vec3 { x, y, z } = axis.normalize();
+        let (s, c) = angle.sin_cos();
+        let t = 1.0 - c;
+        Self::from_rows([[t * x * x + c,     t * x * y - s * z, t * x * z + s * y, 0.0],
+                         [t * x * y + s * z, t * y * y + c,     t * y * z - s * x, 0.0],
+                         [t * x * z - s * y, t * y * z + s * x, t * z * z + c,     0.0],
+                         [0.0,               0.0,               0.0,               1.0]])
+    }
+
+    pub fn scale(factors: Vec3<f32>) -> Self {
+        Self::from_rows([[factors.x, 0.0,       0.0,       0.0],
+                         [0.0,       factors.y, 0.0,       0.0],
+                         [0.0,       0.0,       factors.z, 0.0],
+                         [0.0,       0.0,       0.0,       1.0]])
+    }
+
+    /// View transformation of a camera at `eye` looking along `dir`.
+    ///
+    /// In the resulting view space the camera is at the origin, X axis points
+    /// to the right of the screen, Y axis points up and Z axis points along
+    /// the view direction, so Z is the depth of a point in front of the camera.
+    pub fn look_at(eye: Point3<f32>, dir: Vec3<f32>, up: Vec3<f32>) -> Self {
+        let z_axis = dir.normalize();
+        let y_axis = up.orthogonalize(z_axis)
+                       .expect("Up direction should not be collinear to view direction")
+                       .normalize();
+        let x_axis = z_axis.cross(y_axis);
+        let eye = Vec3::from(eye);
+        Self::from_rows([[x_axis.x, x_axis.y, x_axis.z, -x_axis.dot(eye)],
+                         [y_axis.x, y_axis.y, y_axis.z, -y_axis.dot(eye)],
+                         [z_axis.x, z_axis.y, z_axis.z, -z_axis.dot(eye)],
+                         [0.0,      0.0,      0.0,      1.0]])
+    }
+
+    /// Parallel projection of the view space box onto the [-1, 1] clip cube.
+    pub fn orthographic(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Self {
+        let (w, h, d) = (right - left, top - bottom, far - near);
+        Self::from_rows([[2.0 / w, 0.0,     0.0,     -(right + left) / w],
+                         [0.0,     2.0 / h, 0.0,     -(top + bottom) / h],
+                         [0.0,     0.0,     2.0 / d, -(far + near) / d],
+                         [0.0,     0.0,     0.0,     1.0]])
+    }
+
+    /// Perspective projection of the view space frustum onto the [-1, 1] clip
+    /// cube. The W coordinate of the result is the view space depth.
+    pub fn perspective(fov_y: f32, aspect: f32, near: f32, far: f32) -> Self {
+        let focal = (0.5 * fov_y).tan().recip();
+        let d = far - near;
+        Self::from_rows([[focal / aspect, 0.0,   0.0,               0.0],
+                         [0.0,            focal, 0.0,               0.0],
+                         [0.0,            0.0,   (far + near) / d, -2.0 * far * near / d],
+                         [0.0,            0.0,   1.0,               0.0]])
+    }
+
+    fn minor(&self, row: usize, col: usize) -> f32 {
+        let mut m = [0.0f32; 9];
+        let mut k = 0;
+        for i in (0..4).filter(|&i| i != row) {
+            for j in (0..4).filter(|&j| j != col) {
+                m[k] = self[(i, j)];
+                k += 1;
+            }
+        }
+        m[0] * (m[4] * m[8] - m[5] * m[7]) -
+        m[1] * (m[3] * m[8] - m[5] * m[6]) +
+        m[2] * (m[3] * m[7] - m[4] * m[6])
+    }
+
+    fn cofactor(&self, row: usize, col: usize) -> f32 {
+        let sign = if (row + col).is_multiple_of(2) { 1.0 } else { -1.0 };
+        sign * self.minor(row, col)
+    }
+
+    pub fn determinant(&self) -> f32 {
+        (0..4).map(|j| self[(0, j)] * self.cofactor(0, j)).sum()
+    }
+
+    /// Inverse matrix, `None` if the matrix is singular.
+    pub fn inverse(&self) -> Option<Self> {
+        let det = self.determinant();
+        if det.abs() < EPSILON {
+            return None
+        }
+
+        let mut res = *self;
+        for i in 0..4 {
+            for j in 0..4 {
+                res[(j, i)] = self.cofactor(i, j) / det;
+            }
+        }
+        Some(res)
+    }
+
+    pub fn transform_point(&self, pnt: Point3<f32>) -> Point3<f32> {
+        (*self * HVec4::from(pnt)).to_point3()
+    }
+
+    pub fn transform_vector(&self, v: Vec3<f32>) -> Vec3<f32> {
+        (*self * HVec4::from(v)).to_vec3()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HMat4f, HVec4f};
+    use crate::math::{Point3f, Vec3f};
+
+    fn assert_mat_eq(a: &HMat4f, b: &HMat4f) {
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((a[(i, j)] - b[(i, j)]).abs() < 1e-5, "@ ({}, {}): {:?} != {:?}", i, j, a, b);
+            }
+        }
+    }
+
+    fn assert_pnt_eq(a: Point3f, b: Point3f) {
+        assert!((a.x - b.x).abs() < 1e-5 && (a.y - b.y).abs() < 1e-5 && (a.z - b.z).abs() < 1e-5,
+                "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn multiply_by_identity() {
+        let m = HMat4f::from_rows([[1.0, 2.0, 3.0, 4.0],
+                                   [5.0, 6.0, 7.0, 8.0],
+                                   [9.0, 1.0, 2.0, 3.0],
+                                   [4.0, 5.0, 6.0, 7.0]]);
+        assert_eq!(m * HMat4f::eye(), m);
+        assert_eq!(HMat4f::eye() * m, m);
+        assert_eq!(m.transpose().transpose(), m);
+        assert_eq!(m.transpose()[(0, 1)], 5.0);
+    }
+
+    #[test]
+    fn determinant_and_inverse() {
+        let m = HMat4f::from_rows([[2.0, 0.0, 1.0, 3.0],
+                                   [1.0, 1.0, 0.0, 2.0],
+                                   [0.0, 3.0, 1.0, 1.0],
+                                   [1.0, 0.0, 2.0, 1.0]]);
+        assert!((m.determinant() - (-1.0)).abs() < 1e-5);
+        assert_mat_eq(&(m * m.inverse().unwrap()), &HMat4f::eye());
+
+        let singular = HMat4f::scale(Vec3f { x: 1.0, y: 0.0, z: 1.0 });
+        assert!(singular.inverse().is_none());
+    }
+
+    #[test]
+    fn rotate_about_z() {
+        let rot = HMat4f::rotation(Vec3f { x: 0.0, y: 0.0, z: 1.0 }, std::f32::consts::FRAC_PI_2);
+        let p = rot.transform_point(Point3f { x: 1.0, y: 0.0, z: 0.0 });
+        assert_pnt_eq(p, Point3f { x: 0.0, y: 1.0, z: 0.0 });
+    }
+
+    #[test]
+    fn points_and_vectors_translate_differently() {
+        let tr = HMat4f::translation(Vec3f { x: 1.0, y: 2.0, z: 3.0 });
+        let p = tr.transform_point(Point3f { x: 1.0, y: 1.0, z: 1.0 });
+        assert_pnt_eq(p, Point3f { x: 2.0, y: 3.0, z: 4.0 });
+        let v = tr.transform_vector(Vec3f { x: 1.0, y: 1.0, z: 1.0 });
+        assert_pnt_eq(v.into(), Point3f { x: 1.0, y: 1.0, z: 1.0 });
+    }
+
+    #[test]
+    fn look_at_moves_eye_to_origin() {
+        let eye = Point3f { x: 1.0, y: 2.0, z: 3.0 };
+        let view = HMat4f::look_at(eye,
+                                   Vec3f { x: 0.0, y: 0.0, z: -1.0 },
+                                   Vec3f { x: 0.0, y: 1.0, z: 0.0 });
+        assert_pnt_eq(view.transform_point(eye), Point3f::origin());
+        let ahead = view.transform_point(Point3f { x: 2.0, y: 2.0, z: 1.0 });
+        assert_pnt_eq(ahead, Point3f { x: 1.0, y: 0.0, z: 2.0 });
+    }
+
+    #[test]
+    fn perspective_maps_depth_range_to_clip_cube() {
+        let proj = HMat4f::perspective(std::f32::consts::FRAC_PI_2, 2.0, 1.0, 10.0);
+        let near: HVec4f = proj * HVec4f { x: 1.0, y: 1.0, z: 1.0, w: 1.0 };
+        assert_pnt_eq(near.to_point3(), Point3f { x: 0.5, y: 1.0, z: -1.0 });
+        let far: HVec4f = proj * HVec4f { x: 0.0, y: 0.0, z: 10.0, w: 1.0 };
+        assert!((far.w - 10.0).abs() < 1e-5);
+        assert!((far.to_point3().z - 1.0).abs() < 1e-5);
+
+        let ortho = HMat4f::orthographic(-2.0, 2.0, -1.0, 1.0, 0.0, 4.0);
+        let p = ortho.transform_point(Point3f { x: 2.0, y: -1.0, z: 2.0 });
+        assert_pnt_eq(p, Point3f { x: 1.0, y: -1.0, z: 0.0 });
+    }
+}
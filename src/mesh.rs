@@ -1,4 +1,21 @@
-use crate::math::{Point2f, Point3f, Vec3f};
+use std::collections::HashMap;
+use crate::math::{BndBox3f, Point2f, Point3f, Vec3f};
+
+/// How face normals are weighted when averaged into vertex normals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NormalWeighting {
+    /// Weight by the area of the face.
+    Area,
+    /// Weight by the angle of the face at the vertex.
+    Angle,
+}
+
+/// Directions of increasing U and V texture coordinates on the surface.
+#[derive(Clone, Copy, Debug)]
+pub struct TangentBasis {
+    pub tangent: Vec3f,
+    pub bitangent: Vec3f,
+}
 
 #[derive(Debug)]
 pub struct Triangle {
@@ -13,5 +30,243 @@ pub struct IndexedTriangleMesh {
     pub triangles: Vec<Triangle>,
     pub texcoords: Option<Vec<Point2f>>,
     pub normals: Option<Vec<Vec3f>>,
+    /// Tangent basis at each corner of each triangle, used for normal mapping.
+    pub tangents: Option<Vec<[TangentBasis; 3]>>,
 }
 
+
+impl IndexedTriangleMesh {
+    /// Bounding box of all the vertices of the mesh.
+    pub fn bounding_box(&self) -> BndBox3f {
+        let mut bbox = BndBox3f::new_empty();
+        for &vertex in &self.vertices {
+            bbox.add_point(vertex);
+        }
+        bbox
+    }
+
+    /// Position of the `nth` vertex of triangle `face`.
+    pub fn vertex(&self, face: usize, nth: usize) -> Point3f {
+        self.vertices[(self.triangles[face].vertices[nth] - 1) as usize]
+    }
+
+    /// Texture coordinates of the `nth` vertex of triangle `face`, if any.
+    pub fn texcoord(&self, face: usize, nth: usize) -> Option<Point2f> {
+        let texcoords = self.texcoords.as_ref()?;
+        let indices = self.triangles[face].texcoords.as_ref()?;
+        Some(texcoords[(indices[nth] - 1) as usize])
+    }
+
+    /// Normal of the `nth` vertex of triangle `face` as given in the mesh, if any.
+    pub fn normal(&self, face: usize, nth: usize) -> Option<Vec3f> {
+        let normals = self.normals.as_ref()?;
+        let indices = self.triangles[face].normals.as_ref()?;
+        Some(normals[(indices[nth] - 1) as usize])
+    }
+
+    /// Tangent basis at the `nth` vertex of triangle `face`, if computed.
+    pub fn tangent(&self, face: usize, nth: usize) -> Option<TangentBasis> {
+        self.tangents.as_ref().map(|tangents| tangents[face][nth])
+    }
+
+    /// Tangent basis of triangle `face` derived from its texture coordinates,
+    /// `None` if it has none or they are degenerate. Vectors are not unit.
+    pub fn face_tangent(&self, face: usize) -> Option<TangentBasis> {
+        let uv1 = self.texcoord(face, 0)?;
+        let uv2 = self.texcoord(face, 1)?;
+        let uv3 = self.texcoord(face, 2)?;
+        let duv1 = uv2 - uv1;
+        let duv2 = uv3 - uv1;
+        let det = duv1.x * duv2.y - duv2.x * duv1.y;
+        if det.abs() < 1e-12 {
+            return None
+        }
+
+        let p1 = Vec3f::from(self.vertex(face, 0));
+        let e1 = Vec3f::from(self.vertex(face, 1)) - p1;
+        let e2 = Vec3f::from(self.vertex(face, 2)) - p1;
+        let r = det.recip();
+        Some(TangentBasis {
+            tangent: (e1 * duv2.y - e2 * duv1.y) * r,
+            bitangent: (e2 * duv1.x - e1 * duv2.x) * r,
+        })
+    }
+
+    /// Compute the tangent basis at each triangle corner for normal mapping.
+    ///
+    /// Triangle bases are averaged over the corners sharing both the vertex
+    /// and its texture coordinates, so texture seams are kept intact. Does
+    /// nothing for meshes without texture coordinates.
+    pub fn compute_tangents(&mut self) {
+        if self.texcoords.is_none() {
+            return
+        }
+
+        let zero = Vec3f { x: 0.0, y: 0.0, z: 0.0 };
+        let mut sums: HashMap<(u32, u32), TangentBasis> = HashMap::new();
+        let corner_key = |tri: &Triangle, nth: usize| {
+            (tri.vertices[nth], tri.texcoords.map_or(0, |tc| tc[nth]))
+        };
+        for (face, tri) in self.triangles.iter().enumerate() {
+            if let Some(basis) = self.face_tangent(face) {
+                for nth in 0..3 {
+                    let sum = sums.entry(corner_key(tri, nth))
+                                  .or_insert(TangentBasis { tangent: zero, bitangent: zero });
+                    sum.tangent = sum.tangent + basis.tangent;
+                    sum.bitangent = sum.bitangent + basis.bitangent;
+                }
+            }
+        }
+
+        let normalized = |v: Vec3f| if v.norm() > 1e-6 { v.normalize() } else { v };
+        let tangents = self.triangles.iter().map(|tri| {
+            [0, 1, 2].map(|nth| {
+                let sum = sums.get(&corner_key(tri, nth))
+                              .copied()
+                              .unwrap_or(TangentBasis { tangent: zero, bitangent: zero });
+                TangentBasis { tangent: normalized(sum.tangent), bitangent: normalized(sum.bitangent) }
+            })
+        }).collect();
+        self.tangents = Some(tangents);
+    }
+
+    /// Unit normal of triangle `face` oriented by counter-clockwise winding.
+    pub fn face_normal(&self, face: usize) -> Vec3f {
+        let v1 = self.vertex(face, 0);
+        let v2 = self.vertex(face, 1);
+        let v3 = self.vertex(face, 2);
+        (v2 - v1).cross(v3 - v1).normalize()
+    }
+
+    /// Compute smooth vertex normals and replace the mesh normals with them.
+    ///
+    /// Normal at a triangle corner averages normals of the triangles sharing
+    /// the vertex whose normals deviate from the corner triangle normal by no
+    /// more than `crease_angle` (in radians). Vertices on sharper edges get
+    /// a separate normal for each side, so passing `PI` smoothes everything.
+    pub fn compute_normals(&mut self, weighting: NormalWeighting, crease_angle: f32) {
+        let face_normals: Vec<Vec3f> = (0..self.triangles.len()).map(|face| {
+            let v1 = self.vertex(face, 0);
+            let v2 = self.vertex(face, 1);
+            let v3 = self.vertex(face, 2);
+            let normal = (v2 - v1).cross(v3 - v1);
+            let norm = normal.norm();
+            if norm > 0.0 { normal * norm.recip() } else { normal }
+        }).collect();
+
+        let corner_weight = |face: usize, nth: usize| match weighting {
+            NormalWeighting::Area => {
+                let v1 = self.vertex(face, 0);
+                let v2 = self.vertex(face, 1);
+                let v3 = self.vertex(face, 2);
+                0.5 * (v2 - v1).cross(v3 - v1).norm()
+            },
+            NormalWeighting::Angle => {
+                let v = self.vertex(face, nth);
+                let e1 = v - self.vertex(face, (nth + 1) % 3);
+                let e2 = v - self.vertex(face, (nth + 2) % 3);
+                let (n1, n2) = (e1.norm(), e2.norm());
+                if n1 > 0.0 && n2 > 0.0 {
+                    (e1.dot(e2) / (n1 * n2)).clamp(-1.0, 1.0).acos()
+                } else {
+                    0.0
+                }
+            },
+        };
+
+        let mut incident: Vec<Vec<(usize, f32)>> = vec![vec![]; self.vertices.len()];
+        for (face, tri) in self.triangles.iter().enumerate() {
+            for (nth, v_idx) in tri.vertices.iter().enumerate() {
+                incident[(*v_idx - 1) as usize].push((face, corner_weight(face, nth)));
+            }
+        }
+
+        let cos_crease = crease_angle.cos();
+        let mut normals = vec![];
+        let mut normal_indices = HashMap::new();
+        for face in 0..self.triangles.len() {
+            let face_normal = face_normals[face];
+            let mut indices = [0u32; 3];
+            for (nth, index) in indices.iter_mut().enumerate() {
+                let v_idx = self.triangles[face].vertices[nth];
+                let mut normal = Vec3f { x: 0.0, y: 0.0, z: 0.0 };
+                for &(other, weight) in &incident[(v_idx - 1) as usize] {
+                    if other == face || face_normal.dot(face_normals[other]) >= cos_crease {
+                        normal = normal + face_normals[other] * weight;
+                    }
+                }
+                let norm = normal.norm();
+                let normal = if norm > 0.0 { normal * norm.recip() } else { face_normal };
+
+                // corners of the same vertex with the same normal share it
+                let key = (v_idx, normal.x.to_bits(), normal.y.to_bits(), normal.z.to_bits());
+                *index = *normal_indices.entry(key).or_insert_with(|| {
+                    normals.push(normal);
+                    normals.len() as u32
+                });
+            }
+            self.triangles[face].normals = Some(indices);
+        }
+        self.normals = Some(normals);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NormalWeighting, TangentBasis};
+    use crate::obj::load_obj_file;
+
+    #[test]
+    fn cube_keeps_hard_edges() {
+        let mut cube = load_obj_file("assets/cube.obj").unwrap();
+        cube.compute_normals(NormalWeighting::Area, 30.0f32.to_radians());
+
+        // one normal per vertex of each cube side
+        assert_eq!(cube.normals.as_ref().unwrap().len(), 24);
+        for face in 0..cube.triangles.len() {
+            let face_normal = cube.face_normal(face);
+            for nth in 0..3 {
+                let normal = cube.normal(face, nth).unwrap();
+                assert!(normal.dot(face_normal) > 0.9999, "{:?} != {:?}", normal, face_normal);
+            }
+        }
+    }
+
+    #[test]
+    fn cube_smoothed_by_angle() {
+        let mut cube = load_obj_file("assets/cube.obj").unwrap();
+        cube.compute_normals(NormalWeighting::Angle, std::f32::consts::PI);
+
+        assert_eq!(cube.normals.as_ref().unwrap().len(), 8);
+        for face in 0..cube.triangles.len() {
+            for nth in 0..3 {
+                // angle weighting is not biased by the triangulation of sides
+                let normal = cube.normal(face, nth).unwrap();
+                let vertex = cube.vertex(face, nth);
+                let expected = 3.0f32.sqrt().recip();
+                assert!((normal.x - vertex.x * expected).abs() < 1e-5 &&
+                        (normal.y - vertex.y * expected).abs() < 1e-5 &&
+                        (normal.z - vertex.z * expected).abs() < 1e-5, "{:?} at {:?}", normal, vertex);
+            }
+        }
+    }
+
+    #[test]
+    fn tangents_follow_texture_directions() {
+        let mut cube = load_obj_file("assets/cube_1.obj").unwrap();
+        let basis = cube.face_tangent(0).unwrap();
+        let basis = TangentBasis { tangent: basis.tangent.normalize(), bitangent: basis.bitangent.normalize() };
+        assert!((basis.tangent.x - 1.0).abs() < 1e-5 && basis.tangent.y.abs() < 1e-5);
+        assert!((basis.bitangent.y - 1.0).abs() < 1e-5 && basis.bitangent.x.abs() < 1e-5);
+
+        cube.compute_tangents();
+        assert_eq!(cube.tangents.as_ref().unwrap().len(), cube.triangles.len());
+        for face in 0..cube.triangles.len() {
+            for nth in 0..3 {
+                let basis = cube.tangent(face, nth).unwrap();
+                assert!((basis.tangent.norm() - 1.0).abs() < 1e-5);
+                assert!((basis.bitangent.norm() - 1.0).abs() < 1e-5);
+            }
+        }
+    }
+}
@@ -23,7 +23,7 @@ fn read_coords<const DIM: usize>(split: std::str::Split<char>) -> Option<[f32; D
 
 pub fn load_obj_file(filename: &str) -> Option<IndexedTriangleMesh> {
     let file = File::open(filename);
-    if let Err(_) = file {
+    if file.is_err() {
         return None
     }
 
@@ -32,10 +32,11 @@ pub fn load_obj_file(filename: &str) -> Option<IndexedTriangleMesh> {
         triangles: vec![],
         texcoords: None,
         normals: None,
+        tangents: None,
     };
     let buffered_file = BufReader::new(file.unwrap());
     for l in buffered_file.lines() {
-        if let Err(_) = l {
+        if l.is_err() {
             return Some(res)
         }
 
@@ -1,15 +1,32 @@
+pub mod antialiasing;
+pub mod blend;
 pub mod context;
 pub mod camera;
+pub mod clip;
+pub mod depth;
+pub mod light;
 pub mod line;
+pub mod material;
 pub mod mesh;
 pub mod mesh_wireframe;
+pub mod shader;
+pub mod shadow;
+pub mod texture;
+pub mod tiles;
 pub mod triangle;
 
-pub use context::Context;
-pub use camera::Camera;
-pub use line::draw_line;
-pub use mesh::{draw_mesh, draw_mesh_textured};
-pub use mesh_wireframe::draw_mesh_wireframe;
+pub use antialiasing::{AntiAliasing, SampleCount};
+pub use blend::BlendMode;
+pub use context::{Context, CullMode, FrontFace, ShadingMode};
+pub use camera::{Camera, Projection};
+pub use depth::DepthBuffer;
+pub use light::{Attenuation, Light, LightKind};
+pub use line::{clip_line, draw_line, draw_line_aa, draw_line_float, draw_line_styled, LineCap, LineStyle};
+pub use material::Material;
+pub use mesh::{draw_mesh, draw_mesh_textured, draw_mesh_with_depth, draw_mesh_with_shader};
+pub use mesh_wireframe::{draw_mesh_wireframe, draw_mesh_wireframe_with, PolygonOffset, WireframeEdges, WireframeOptions};
+pub use shader::{FlatShader, Fragment, Shader, TexturedShader, Varyings};
+pub use shadow::ShadowMap;
+pub use texture::{Filter, Sampler, Texture, WrapMode};
 pub use triangle::draw_triangle;
 
-use triangle::{draw_3d_triangle, draw_3d_triangle_textured};
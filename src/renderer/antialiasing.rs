@@ -0,0 +1,76 @@
+/// Number of samples per pixel of anti-aliased rendering.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleCount {
+    X2,
+    X4,
+    X8,
+}
+
+/// Anti-aliasing of the triangle edges.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AntiAliasing {
+    None,
+    /// Multisampling: coverage and depth are tested per sample, while the
+    /// fragment stage runs once per pixel.
+    Msaa(SampleCount),
+    /// Supersampling: the fragment stage runs for every sample, slower than
+    /// multisampling but also smooths the shading.
+    Ssaa(SampleCount),
+}
+
+// rotated grid sample positions in 1/16 of a pixel relative to its center,
+// no two samples share a row or a column
+const PATTERN_1X: [(i32, i32); 1] = [(0, 0)];
+const PATTERN_2X: [(i32, i32); 2] = [(4, 4), (-4, -4)];
+const PATTERN_4X: [(i32, i32); 4] = [(-2, -6), (6, -2), (-6, 2), (2, 6)];
+const PATTERN_8X: [(i32, i32); 8] = [(1, -3), (-1, 3), (5, 1), (-3, -5), (-5, 5), (-7, -1), (3, 7), (7, -7)];
+
+impl AntiAliasing {
+    fn sample_count(&self) -> Option<SampleCount> {
+        match *self {
+            AntiAliasing::None => None,
+            AntiAliasing::Msaa(count) | AntiAliasing::Ssaa(count) => Some(count),
+        }
+    }
+
+    /// Sample positions within a pixel in 1/16 of a pixel relative to its
+    /// center.
+    pub fn sample_offsets(&self) -> &'static [(i32, i32)] {
+        match self.sample_count() {
+            None => &PATTERN_1X,
+            Some(SampleCount::X2) => &PATTERN_2X,
+            Some(SampleCount::X4) => &PATTERN_4X,
+            Some(SampleCount::X8) => &PATTERN_8X,
+        }
+    }
+
+    pub fn samples(&self) -> usize {
+        self.sample_offsets().len()
+    }
+
+    /// Whether the fragment stage runs for every sample.
+    pub fn shades_samples(&self) -> bool {
+        matches!(self, AntiAliasing::Ssaa(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AntiAliasing, SampleCount};
+
+    #[test]
+    fn rotated_grid_patterns() {
+        for count in [SampleCount::X2, SampleCount::X4, SampleCount::X8] {
+            let offsets = AntiAliasing::Msaa(count).sample_offsets();
+            assert_eq!(offsets, AntiAliasing::Ssaa(count).sample_offsets());
+            for (i, a) in offsets.iter().enumerate() {
+                assert!(a.0.abs() < 8 && a.1.abs() < 8, "{:?} is outside of the pixel", a);
+                for b in &offsets[i + 1..] {
+                    assert!(a.0 != b.0 && a.1 != b.1, "{:?} and {:?} are on the same row or column", a, b);
+                }
+            }
+        }
+        assert_eq!(AntiAliasing::None.samples(), 1);
+        assert_eq!(AntiAliasing::Msaa(SampleCount::X8).samples(), 8);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::tgaimage::TGAColor;
+
+/// How the fragment colors are combined with the colors already in the
+/// render target.
+///
+/// Colors without an alpha component are opaque. The result keeps the
+/// number of components of the target color.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Overwrite the target color.
+    #[default]
+    Replace,
+    /// Fragment color over the target color weighted by the fragment alpha.
+    AlphaOver,
+    /// Fragment color scaled by its alpha added to the target color.
+    Additive,
+    /// Target color multiplied by the fragment color, the fragment alpha
+    /// blends between the product and the unchanged target color.
+    Multiply,
+    /// Same as `AlphaOver` for fragment colors already multiplied by their
+    /// alpha.
+    Premultiplied,
+}
+
+impl BlendMode {
+    /// Whether the result depends on the target color, in which case the
+    /// triangles are drawn back to front.
+    pub fn is_blending(&self) -> bool {
+        *self != BlendMode::Replace
+    }
+
+    /// Combine the fragment color `src` with the target color `dst`.
+    pub fn blend(&self, src: TGAColor, dst: TGAColor) -> TGAColor {
+        if *self == BlendMode::Replace {
+            return src
+        }
+        let alpha = |c: &TGAColor| if c.bytespp() == 4 { c.a() as f32 / 255.0 } else { 1.0 };
+        let src_alpha = alpha(&src);
+        let dst_alpha = alpha(&dst);
+
+        let mut res = [0u8; 4];
+        for (i, res) in res.iter_mut().enumerate().take(3) {
+            let s = src.components().get(i).copied().unwrap_or(0) as f32 / 255.0;
+            let d = dst.components().get(i).copied().unwrap_or(0) as f32 / 255.0;
+            let value = match self {
+                BlendMode::Replace => unreachable!(),
+                BlendMode::AlphaOver => s * src_alpha + d * (1.0 - src_alpha),
+                BlendMode::Additive => d + s * src_alpha,
+                BlendMode::Multiply => d * (s * src_alpha + 1.0 - src_alpha),
+                BlendMode::Premultiplied => s + d * (1.0 - src_alpha),
+            };
+            *res = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+        res[3] = match self {
+            BlendMode::AlphaOver | BlendMode::Premultiplied => {
+                ((src_alpha + dst_alpha * (1.0 - src_alpha)) * 255.0).round() as u8
+            },
+            _ => dst.a(),
+        };
+        TGAColor::from_component_slice(&res, dst.bytespp())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlendMode;
+    use crate::tgaimage::TGAColor;
+
+    #[test]
+    fn blend_modes() {
+        let dst = TGAColor::from_rgb(100, 200, 40);
+        let src = TGAColor::from_rgba(255, 0, 100, 128);
+        let blend = |mode: BlendMode| mode.blend(src, dst);
+
+        assert_eq!(blend(BlendMode::Replace), src);
+        assert_eq!(blend(BlendMode::AlphaOver), TGAColor::from_rgb(178, 100, 70));
+        assert_eq!(blend(BlendMode::Additive), TGAColor::from_rgb(228, 200, 90));
+        assert_eq!(blend(BlendMode::Multiply), TGAColor::from_rgb(100, 100, 28));
+        assert_eq!(blend(BlendMode::Premultiplied), TGAColor::from_rgb(255, 100, 120));
+
+        // colors without alpha are opaque
+        let opaque = TGAColor::from_rgb(10, 20, 30);
+        assert_eq!(BlendMode::AlphaOver.blend(opaque, dst), opaque);
+        assert_eq!(BlendMode::Multiply.blend(opaque, dst), TGAColor::from_rgb(4, 16, 5));
+
+        // alpha of the target accumulates
+        let under = TGAColor::from_rgba(0, 0, 0, 128);
+        assert_eq!(BlendMode::AlphaOver.blend(src, under).a(), 192);
+    }
+}
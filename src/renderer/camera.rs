@@ -1,39 +1,122 @@
 use crate::math::{Point2f, Point3f, Vec3f};
-#[derive(Debug)]
+use crate::math::hvec::{HMat4f, HVec4f};
+
+/// Kind of projection a camera applies to map view space onto the screen.
+#[derive(Clone, Copy, Debug)]
+pub enum Projection {
+    /// Parallel projection, `zoom` scales view space X and Y into the
+    /// [-1, 1] screen range. `near` and `far` bound the visible depth range
+    /// and may be negative to keep geometry behind the camera location.
+    Orthographic { zoom: f32, near: f32, far: f32 },
+    /// Pinhole projection with vertical field of view `fov_y` (in radians)
+    /// and `aspect` = width / height of the screen.
+    Perspective { fov_y: f32, aspect: f32, near: f32, far: f32 },
+}
+
+#[derive(Clone, Debug)]
 pub struct Camera {
     loc: Point3f,
     dir: Vec3f,
     up: Vec3f,
-    ref_dir: Vec3f,
-    zoom: f32,
+    projection: Projection,
+    view: HMat4f,
+    proj: HMat4f,
+    view_proj: HMat4f,
 }
 
 impl Camera {
-    pub fn new(loc: Point3f, dir: Vec3f, up: Vec3f, zoom: f32) -> Camera {
+    pub fn new(loc: Point3f, dir: Vec3f, up: Vec3f, projection: Projection) -> Camera {
         let dir = dir.normalize();
         if let Some(up) = up.orthogonalize(dir) {
             let up = up.normalize();
-            let ref_dir = dir.cross(up);
-            Camera { loc, dir, up, ref_dir, zoom }
+            let view = HMat4f::look_at(loc, dir, up);
+            let proj = Self::make_projection_matrix(projection);
+            Camera { loc, dir, up, projection, view, proj, view_proj: proj * view }
         } else {
             panic!("Wrong params for camera given");
         }
     }
 
-    /// Transform a point in 3D space into the screen space coordinates
-    /// with orthogonal transformation.
+    pub fn orthographic(loc: Point3f, dir: Vec3f, up: Vec3f, zoom: f32, near: f32, far: f32) -> Camera {
+        Self::new(loc, dir, up, Projection::Orthographic { zoom, near, far })
+    }
+
+    pub fn perspective(loc: Point3f,
+                       dir: Vec3f,
+                       up: Vec3f,
+                       fov_y: f32,
+                       aspect: f32,
+                       near: f32,
+                       far: f32) -> Camera {
+        assert!(near > 0.0 && far > near, "Wrong depth range for perspective camera given");
+        Self::new(loc, dir, up, Projection::Perspective { fov_y, aspect, near, far })
+    }
+
+    pub fn location(&self) -> Point3f {
+        self.loc
+    }
+
+    pub fn direction(&self) -> Vec3f {
+        self.dir
+    }
+
+    pub fn up(&self) -> Vec3f {
+        self.up
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    /// Range of view depths (see [`Camera::transform`]) visible to the camera.
+    pub fn depth_range(&self) -> (f32, f32) {
+        match self.projection {
+            Projection::Orthographic { near, far, .. } => (near, far),
+            Projection::Perspective { near, far, .. } => (near, far),
+        }
+    }
+
+    /// Transformation from world space into the view space of the camera.
+    pub fn view_matrix(&self) -> HMat4f {
+        self.view
+    }
+
+    /// Transformation from view space into clip space.
+    pub fn projection_matrix(&self) -> HMat4f {
+        self.proj
+    }
+
+    fn make_projection_matrix(projection: Projection) -> HMat4f {
+        match projection {
+            Projection::Orthographic { zoom, near, far } => {
+                let half_size = zoom.recip();
+                HMat4f::orthographic(-half_size, half_size, -half_size, half_size, near, far)
+            },
+            Projection::Perspective { fov_y, aspect, near, far } => {
+                HMat4f::perspective(fov_y, aspect, near, far)
+            },
+        }
+    }
+
+    /// Transformation from world space into clip space. The matrices are
+    /// computed once when the camera is built, so the vertex stages can get
+    /// this one for every vertex.
+    pub fn view_projection_matrix(&self) -> HMat4f {
+        self.view_proj
+    }
+
+    /// Transform a point in 3D space into the screen space coordinates.
     ///
     /// Screen space coordinates are defined by camera location, view and
     /// up directions. The XOY plane of screen space coordinates is the screen
     /// plane and its [-1,1] x [-1,1] region corresponds to the screen where
     /// the scene will be displayed. The Z coordinate is signed distance to
-    /// the screen plane.
+    /// the screen plane. For perspective cameras X and Y are divided by Z,
+    /// so the result is only meaningful for points in front of the camera.
     pub fn transform(&self, pnt: &Point3f) -> Point3f {
-        let rad_vec: Vec3f = Vec3f::from(*pnt) - self.loc.into();
-        let z = rad_vec.dot(self.dir);
-        let x = rad_vec.dot(self.ref_dir) * self.zoom;
-        let y = rad_vec.dot(self.up) * self.zoom;
-        Point3f { x, y, z }
+        let view_pnt = self.view_matrix() * HVec4f::from(*pnt);
+        let clip_pnt = self.projection_matrix() * view_pnt;
+        Point3f { x: clip_pnt.x / clip_pnt.w, y: clip_pnt.y / clip_pnt.w, z: view_pnt.z }
     }
 
     /// Project a point in 3D space onto the screen space.
@@ -42,3 +125,24 @@ impl Camera {
         trf_pnt.drop_z()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Camera;
+    use crate::math::{Point3f, Vec3f};
+
+    #[test]
+    fn perspective_projection() {
+        let camera = Camera::perspective(Point3f { x: 1.0, y: 2.0, z: 3.0 },
+                                         Vec3f { x: 0.0, y: 0.0, z: -1.0 },
+                                         Vec3f { x: 0.0, y: 1.0, z: 0.0 },
+                                         90.0f32.to_radians(), 2.0, 1.0, 10.0);
+        // X and Y are divided by the depth and X by the aspect ratio
+        let p = camera.transform(&Point3f { x: 2.0, y: 2.5, z: 1.0 });
+        assert!((p.x - 0.25).abs() < 1e-5 && (p.y - 0.25).abs() < 1e-5 && (p.z - 2.0).abs() < 1e-5, "{:?}", p);
+
+        let ndc_depth = |z: f32| camera.view_projection_matrix().transform_point(Point3f { x: 1.0, y: 2.0, z }).z;
+        assert!((ndc_depth(2.0) + 1.0).abs() < 1e-5, "{}", ndc_depth(2.0));
+        assert!((ndc_depth(-7.0) - 1.0).abs() < 1e-5, "{}", ndc_depth(-7.0));
+    }
+}
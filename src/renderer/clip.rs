@@ -0,0 +1,185 @@
+use crate::math::Vec3f;
+use crate::math::hvec::HVec4f;
+
+/// Vertex of a triangle being clipped.
+#[derive(Clone, Copy, Debug)]
+pub struct ClipVertex {
+    /// Position in clip space.
+    pub pos: HVec4f,
+    /// Barycentric coordinates of the vertex relative to the original
+    /// triangle, used to interpolate vertex attributes of clipped vertices.
+    pub bary: Vec3f,
+}
+
+impl ClipVertex {
+    /// Vertices of an unclipped triangle with clip space positions `pos`.
+    pub fn triangle(pos: [HVec4f; 3]) -> [ClipVertex; 3] {
+        [
+            ClipVertex { pos: pos[0], bary: Vec3f { x: 1.0, y: 0.0, z: 0.0 } },
+            ClipVertex { pos: pos[1], bary: Vec3f { x: 0.0, y: 1.0, z: 0.0 } },
+            ClipVertex { pos: pos[2], bary: Vec3f { x: 0.0, y: 0.0, z: 1.0 } },
+        ]
+    }
+
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        ClipVertex {
+            pos: self.pos.lerp(other.pos, t),
+            bary: self.bary + (other.bary - self.bary) * t,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ClipPlane {
+    Near,
+    Far,
+    Left(f32),
+    Right(f32),
+    Bottom(f32),
+    Top(f32),
+}
+
+impl ClipPlane {
+    /// Signed distance-like value, non-negative on the visible side.
+    fn distance(&self, p: &HVec4f) -> f32 {
+        match *self {
+            ClipPlane::Near => p.z + p.w,
+            ClipPlane::Far => p.w - p.z,
+            ClipPlane::Left(g) => p.x + g * p.w,
+            ClipPlane::Right(g) => g * p.w - p.x,
+            ClipPlane::Bottom(g) => p.y + g * p.w,
+            ClipPlane::Top(g) => g * p.w - p.y,
+        }
+    }
+}
+
+fn clip_polygon(polygon: &[ClipVertex], plane: ClipPlane) -> Vec<ClipVertex> {
+    let mut res = Vec::with_capacity(polygon.len() + 1);
+    for (i, cur) in polygon.iter().enumerate() {
+        let next = &polygon[(i + 1) % polygon.len()];
+        let d_cur = plane.distance(&cur.pos);
+        let d_next = plane.distance(&next.pos);
+        if d_cur >= 0.0 {
+            res.push(*cur);
+        }
+        if (d_cur >= 0.0) != (d_next >= 0.0) {
+            res.push(cur.lerp(next, d_cur / (d_cur - d_next)));
+        }
+    }
+    res
+}
+
+/// Clip a triangle against the near and far planes of the clip volume with
+/// Sutherland-Hodgman algorithm.
+///
+/// With `guard_band` set to `Some(g)` the polygon is also clipped against
+/// side planes at `|x| = g * w` and `|y| = g * w`, which keeps screen
+/// coordinates of the result within `g` times the screen size. The parts
+/// of the polygon between the guard band and the screen are left for the
+/// rasterizer to discard. Returns vertices of a convex polygon in the
+/// original winding order, empty if nothing is left after clipping.
+pub fn clip_triangle(tri: &[ClipVertex; 3], guard_band: Option<f32>) -> Vec<ClipVertex> {
+    let mut planes = vec![ClipPlane::Near, ClipPlane::Far];
+    if let Some(g) = guard_band {
+        planes.extend([ClipPlane::Left(g), ClipPlane::Right(g), ClipPlane::Bottom(g), ClipPlane::Top(g)]);
+    }
+
+    let mut polygon = tri.to_vec();
+    for plane in planes {
+        if polygon.iter().all(|v| plane.distance(&v.pos) >= 0.0) {
+            continue;
+        }
+        polygon = clip_polygon(&polygon, plane);
+        if polygon.len() < 3 {
+            return vec![]
+        }
+    }
+    polygon
+}
+
+/// Clip a segment against the near and far planes of the clip volume,
+/// returns the visible part of it if any.
+pub fn clip_segment(p1: HVec4f, p2: HVec4f) -> Option<(HVec4f, HVec4f)> {
+    let (mut t_min, mut t_max) = (0.0f32, 1.0f32);
+    for plane in [ClipPlane::Near, ClipPlane::Far] {
+        let d1 = plane.distance(&p1);
+        let d2 = plane.distance(&p2);
+        if d1 < 0.0 && d2 < 0.0 {
+            return None
+        } else if d1 < 0.0 {
+            t_min = t_min.max(d1 / (d1 - d2));
+        } else if d2 < 0.0 {
+            t_max = t_max.min(d1 / (d1 - d2));
+        }
+    }
+
+    if t_min > t_max {
+        None
+    } else {
+        Some((p1.lerp(p2, t_min), p1.lerp(p2, t_max)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clip_segment, clip_triangle, ClipVertex};
+    use crate::math::hvec::HVec4f;
+
+    fn hvec(x: f32, y: f32, z: f32, w: f32) -> HVec4f {
+        HVec4f { x, y, z, w }
+    }
+
+    #[test]
+    fn triangle_inside_is_untouched() {
+        let tri = ClipVertex::triangle([hvec(0.0, 0.0, 0.0, 1.0),
+                                        hvec(0.5, 0.0, 0.5, 1.0),
+                                        hvec(0.0, 0.5, -0.5, 1.0)]);
+        let polygon = clip_triangle(&tri, Some(2.0));
+        assert_eq!(polygon.len(), 3);
+        assert_eq!(polygon[1].pos, tri[1].pos);
+    }
+
+    #[test]
+    fn triangle_behind_near_plane_is_dropped() {
+        let tri = ClipVertex::triangle([hvec(0.0, 0.0, -2.0, 1.0),
+                                        hvec(0.5, 0.0, -3.0, 1.0),
+                                        hvec(0.0, 0.5, -1.5, 1.0)]);
+        assert!(clip_triangle(&tri, None).is_empty());
+    }
+
+    #[test]
+    fn triangle_crossing_near_plane_becomes_quad() {
+        let tri = ClipVertex::triangle([hvec(0.0, 0.0, -3.0, 1.0),
+                                        hvec(1.0, 0.0, 0.0, 1.0),
+                                        hvec(0.0, 1.0, 0.0, 1.0)]);
+        let polygon = clip_triangle(&tri, None);
+        assert_eq!(polygon.len(), 4);
+        for v in polygon {
+            assert!(v.pos.z >= -v.pos.w - 1e-6);
+            let bary_sum = v.bary.x + v.bary.y + v.bary.z;
+            assert!((bary_sum - 1.0).abs() < 1e-6);
+            // position is reproduced from the original vertices
+            let x = tri[0].pos.x * v.bary.x + tri[1].pos.x * v.bary.y + tri[2].pos.x * v.bary.z;
+            assert!((x - v.pos.x).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn guard_band_clips_side_planes() {
+        let tri = ClipVertex::triangle([hvec(-10.0, 0.0, 0.0, 1.0),
+                                        hvec(10.0, 0.0, 0.0, 1.0),
+                                        hvec(0.0, 1.0, 0.0, 1.0)]);
+        assert_eq!(clip_triangle(&tri, None).len(), 3);
+        let polygon = clip_triangle(&tri, Some(2.0));
+        assert_eq!(polygon.len(), 5);
+        assert!(polygon.iter().all(|v| v.pos.x.abs() <= 2.0 + 1e-6));
+    }
+
+    #[test]
+    fn segment_is_cut_at_far_plane() {
+        let (p1, p2) = clip_segment(hvec(0.0, 0.0, 0.0, 1.0), hvec(0.0, 0.0, 3.0, 1.0)).unwrap();
+        assert_eq!(p1.z, 0.0);
+        assert!((p2.z - 1.0).abs() < 1e-6);
+        assert!(clip_segment(hvec(0.0, 0.0, 2.0, 1.0), hvec(0.0, 0.0, 3.0, 1.0)).is_none());
+    }
+}
@@ -1,7 +1,77 @@
-use crate::renderer::Camera;
+use crate::renderer::{AntiAliasing, Camera, Light};
 use crate::math::Vec3f;
 
+/// How light intensity is computed across a triangle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShadingMode {
+    /// Geometric normal of the triangle, lights are still evaluated per
+    /// pixel so point lights and shadows vary across it.
+    Flat,
+    /// Light computed at the vertices, shadows included, and interpolated
+    /// across the triangle.
+    Gouraud,
+    /// Normal interpolated across the triangle and intensity computed per pixel.
+    Phong,
+}
+
+/// Triangles skipped by the rasterizer depending on which side of them
+/// faces the camera.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CullMode {
+    /// Skip the triangles seen from the back.
+    Back,
+    /// Skip the triangles seen from the front.
+    Front,
+    /// Draw all the triangles.
+    None,
+}
+
+/// Winding of the front side of triangles as seen on the screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrontFace {
+    /// Vertices go counter-clockwise, as in OBJ files.
+    Ccw,
+    /// Vertices go clockwise.
+    Cw,
+}
+
 pub struct Context {
     pub camera: Camera,
-    pub light: Vec3f,
+    /// Lights of the built-in shaders, their contributions add up.
+    pub lights: Vec<Light>,
+    /// Size of the guard band in screen sizes. Triangles are clipped against
+    /// its edges instead of the screen edges, `None` clips them only at the
+    /// widest guard band the rasterizer's fixed point coordinates allow.
+    pub guard_band: Option<f32>,
+    /// Shading mode of the built-in shaders. Smooth modes use vertex normals
+    /// of the mesh and fall back to the triangle normals if there are none.
+    pub shading: ShadingMode,
+    /// Intensity of the white light reaching all the surfaces regardless of
+    /// the lights and shadows, used by the built-in shaders.
+    pub ambient: f32,
+    pub cull_mode: CullMode,
+    pub front_face: FrontFace,
+    /// Anti-aliasing of the meshes, depth buffers they are drawn with need
+    /// to have a matching number of samples.
+    pub anti_aliasing: AntiAliasing,
+}
+
+impl Context {
+    pub const DEFAULT_GUARD_BAND: f32 = 4.0;
+    pub const DEFAULT_AMBIENT: f32 = 0.1;
+
+    /// Context lit by a single white directional light traveling along
+    /// `light`.
+    pub fn new(camera: Camera, light: Vec3f) -> Context {
+        Context {
+            camera,
+            lights: vec![Light::directional(light)],
+            guard_band: Some(Self::DEFAULT_GUARD_BAND),
+            shading: ShadingMode::Flat,
+            ambient: Self::DEFAULT_AMBIENT,
+            cull_mode: CullMode::Back,
+            front_face: FrontFace::Ccw,
+            anti_aliasing: AntiAliasing::None,
+        }
+    }
 }
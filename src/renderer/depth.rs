@@ -0,0 +1,139 @@
+use crate::tgaimage::{tga_format, TGAColor, TGAImage};
+
+/// Per-pixel depth values of a render target, smaller values are closer to
+/// the camera. Can be kept between draw calls to share depth testing.
+///
+/// Anti-aliased rendering keeps a depth value and a color for every sample
+/// of a pixel, the pixels covered by several draw calls are resolved from
+/// the samples of all of them. Pixels drawn over by other means in between
+/// get the resolved color back when a later draw call covers them.
+#[derive(Clone, Debug)]
+pub struct DepthBuffer {
+    data: Vec<f32>,
+    /// Colors of the samples with more than one sample per pixel, `None`
+    /// until the pixel is drawn to.
+    colors: Vec<Option<TGAColor>>,
+    samples: usize,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl DepthBuffer {
+    /// Depth of the pixels nothing was drawn to.
+    pub const EMPTY: f32 = f32::MAX;
+
+    pub fn with_size(w: i32, h: i32) -> Self {
+        Self::with_samples(w, h, 1)
+    }
+
+    pub fn with_samples(w: i32, h: i32, samples: usize) -> Self {
+        assert!(samples > 0, "depth buffer needs at least one sample per pixel");
+        let size = (w * h) as usize * samples;
+        DepthBuffer {
+            data: vec![Self::EMPTY; size],
+            colors: if samples > 1 { vec![None; size] } else { vec![] },
+            samples,
+            width: w,
+            height: h,
+        }
+    }
+
+    /// Number of samples per pixel.
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    pub fn clear(&mut self) {
+        self.data.fill(Self::EMPTY);
+        self.colors.fill(None);
+    }
+
+    fn offset(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            None
+        } else {
+            Some((x + y * self.width) as usize * self.samples)
+        }
+    }
+
+    /// Depth of the closest sample of the pixel.
+    pub fn get(&self, x: i32, y: i32) -> Option<f32> {
+        self.offset(x, y).map(|offset| {
+            self.data[offset..offset + self.samples].iter().copied().fold(Self::EMPTY, f32::min)
+        })
+    }
+
+    /// Set the depth of all the samples of the pixel.
+    pub fn set(&mut self, x: i32, y: i32, depth: f32) -> bool {
+        if let Some(offset) = self.offset(x, y) {
+            self.data[offset..offset + self.samples].fill(depth);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn get_sample(&self, x: i32, y: i32, sample: usize) -> Option<f32> {
+        assert!(sample < self.samples);
+        self.offset(x, y).map(|offset| self.data[offset + sample])
+    }
+
+    pub fn set_sample(&mut self, x: i32, y: i32, sample: usize, depth: f32) -> bool {
+        assert!(sample < self.samples);
+        if let Some(offset) = self.offset(x, y) {
+            self.data[offset + sample] = depth;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Colors of the samples of the pixel, empty with a single sample per
+    /// pixel.
+    pub(crate) fn sample_colors(&self, x: i32, y: i32) -> Option<&[Option<TGAColor>]> {
+        let offset = self.offset(x, y)?;
+        Some(self.colors.get(offset..offset + self.samples).unwrap_or_default())
+    }
+
+    pub(crate) fn sample_colors_mut(&mut self, x: i32, y: i32) -> Option<&mut [Option<TGAColor>]> {
+        let offset = self.offset(x, y)?;
+        Some(self.colors.get_mut(offset..offset + self.samples).unwrap_or_default())
+    }
+
+    /// Depth test: store `depth` in the samples of the pixel it is closer
+    /// than, returns `true` if there were any.
+    pub fn test_and_set(&mut self, x: i32, y: i32, depth: f32) -> bool {
+        let Some(offset) = self.offset(x, y) else {
+            return false
+        };
+        let mut passed = false;
+        for stored in &mut self.data[offset..offset + self.samples] {
+            if *stored > depth {
+                *stored = depth;
+                passed = true;
+            }
+        }
+        passed
+    }
+
+    /// Grayscale image of the depth values for debugging. The closest pixel
+    /// is white, the farthest one is dark gray and empty pixels are black.
+    pub fn to_tga(&self) -> TGAImage {
+        let drawn = self.data.iter().copied().filter(|&d| d != Self::EMPTY);
+        let (min, max) = drawn.fold((f32::MAX, f32::MIN), |(min, max), d| (min.min(d), max.max(d)));
+        let range = if max > min { max - min } else { 1.0 };
+
+        let mut image = TGAImage::with_size(self.width, self.height, tga_format::GRAYSCALE);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let depth = self.get(x, y).unwrap();
+                if depth == Self::EMPTY {
+                    continue;
+                }
+                let value = (255.0 - 191.0 * (depth - min) / range) as u8;
+                image.set(x, y, TGAColor::from_rgb(value, value, value)).unwrap();
+            }
+        }
+        image
+    }
+}
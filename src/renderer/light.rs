@@ -0,0 +1,182 @@
+use crate::math::{Point3f, Vec3f};
+use crate::mesh::IndexedTriangleMesh;
+use crate::tgaimage::TGAColor;
+use super::ShadowMap;
+
+/// Where the light comes from.
+#[derive(Clone, Copy, Debug)]
+pub enum LightKind {
+    /// Light from far away, all of it travels along `direction`.
+    Directional { direction: Vec3f },
+    /// Light spreading in all directions from `position`.
+    Point { position: Point3f },
+    /// Point light limited to a cone around `direction`. `cone_angle` is the
+    /// angle in radians between the axis and the edge of the cone, the light
+    /// fades out over the outer `falloff` radians of it.
+    Spot { position: Point3f, direction: Vec3f, cone_angle: f32, falloff: f32 },
+}
+
+/// Distance attenuation of point and spot lights, the light is divided by
+/// `constant + linear * d + quadratic * d * d` at distance `d`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Attenuation {
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+impl Attenuation {
+    pub const NONE: Attenuation = Attenuation { constant: 1.0, linear: 0.0, quadratic: 0.0 };
+    pub const INVERSE_SQUARE: Attenuation = Attenuation { constant: 0.0, linear: 0.0, quadratic: 1.0 };
+
+    /// Factor the light is scaled by at `distance` from its source.
+    pub fn factor(&self, distance: f32) -> f32 {
+        let divisor = self.constant + self.linear * distance + self.quadratic * distance * distance;
+        divisor.max(1e-6).recip()
+    }
+}
+
+impl Default for Attenuation {
+    fn default() -> Self {
+        Attenuation::NONE
+    }
+}
+
+/// Light source lighting the surfaces drawn by the built-in shaders.
+#[derive(Clone, Debug)]
+pub struct Light {
+    kind: LightKind,
+    pub color: TGAColor,
+    /// Scale of the color, values above 1 give lights brighter than white.
+    pub intensity: f32,
+    /// Ignored by directional lights.
+    pub attenuation: Attenuation,
+    /// Shadow map rendered along the direction of the light, only
+    /// directional lights have one.
+    shadow: Option<ShadowMap>,
+}
+
+impl Light {
+    fn with_kind(kind: LightKind) -> Light {
+        Light {
+            kind,
+            color: TGAColor::from_rgb(255, 255, 255),
+            intensity: 1.0,
+            attenuation: Attenuation::NONE,
+            shadow: None,
+        }
+    }
+
+    /// White light traveling along `direction`.
+    pub fn directional(direction: Vec3f) -> Light {
+        Light::with_kind(LightKind::Directional { direction: direction.normalize() })
+    }
+
+    /// White light traveling along `direction` with a `size` x `size` shadow
+    /// map of the mesh, the built-in shaders darken the fragments it occludes.
+    pub fn directional_with_shadow(direction: Vec3f, mesh: &IndexedTriangleMesh, size: i32) -> Light {
+        let mut light = Light::directional(direction);
+        light.shadow = Some(ShadowMap::for_mesh(mesh, direction, size));
+        light
+    }
+
+    /// White light at `position`, not attenuated.
+    pub fn point(position: Point3f) -> Light {
+        Light::with_kind(LightKind::Point { position })
+    }
+
+    /// White light at `position` shining along `direction`, not attenuated.
+    pub fn spot(position: Point3f, direction: Vec3f, cone_angle: f32, falloff: f32) -> Light {
+        Light::with_kind(LightKind::Spot { position, direction: direction.normalize(), cone_angle, falloff })
+    }
+
+    pub fn kind(&self) -> LightKind {
+        self.kind
+    }
+
+    pub fn shadow(&self) -> Option<&ShadowMap> {
+        self.shadow.as_ref()
+    }
+
+    /// Shadow map of the light, to tune it or to add shadow casters.
+    pub fn shadow_mut(&mut self) -> Option<&mut ShadowMap> {
+        self.shadow.as_mut()
+    }
+
+    /// Unit direction the light travels at the world space point and the
+    /// factor its color is scaled by there, `None` if it does not reach the
+    /// point.
+    pub fn incidence(&self, pnt: Point3f) -> Option<(Vec3f, f32)> {
+        let position = match self.kind {
+            LightKind::Directional { direction } => return Some((direction.normalize(), self.intensity)),
+            LightKind::Point { position } | LightKind::Spot { position, .. } => position,
+        };
+        let to_point = Vec3f::from(pnt) - Vec3f::from(position);
+        let distance = to_point.norm();
+        if distance < 1e-6 {
+            return None
+        }
+        let direction = to_point * distance.recip();
+
+        let mut factor = self.intensity * self.attenuation.factor(distance);
+        if let LightKind::Spot { direction: axis, cone_angle, falloff, .. } = self.kind {
+            factor *= cone_factor(axis.normalize().dot(direction), cone_angle, falloff);
+        }
+        (factor > 0.0).then_some((direction, factor))
+    }
+
+    /// Red, green and blue components of the color in [0, 1].
+    pub(crate) fn color_factors(&self) -> Vec3f {
+        let c = &self.color;
+        Vec3f { x: c.r() as f32, y: c.g() as f32, z: c.b() as f32 } * 255.0f32.recip()
+    }
+}
+
+/// Spot light factor of a direction at angle `acos(cos_angle)` from the
+/// axis, smoothly fading from 1 to 0 over the `falloff` edge of the cone.
+fn cone_factor(cos_angle: f32, cone_angle: f32, falloff: f32) -> f32 {
+    let outer = cone_angle.cos();
+    let inner = (cone_angle - falloff.max(0.0)).max(0.0).cos();
+    if cos_angle >= inner {
+        1.0
+    } else if cos_angle <= outer {
+        0.0
+    } else {
+        let t = (cos_angle - outer) / (inner - outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Attenuation, Light};
+    use crate::math::{Point3f, Vec3f};
+
+    #[test]
+    fn point_light_attenuates_with_distance() {
+        let mut light = Light::point(Point3f { x: 0.0, y: 2.0, z: 0.0 });
+        light.attenuation = Attenuation::INVERSE_SQUARE;
+        let (direction, factor) = light.incidence(Point3f { x: 0.0, y: 0.0, z: 0.0 }).unwrap();
+        assert!((direction - Vec3f { x: 0.0, y: -1.0, z: 0.0 }).norm() < 1e-6);
+        assert_eq!(factor, 0.25);
+
+        light.attenuation = Attenuation::NONE;
+        light.intensity = 2.0;
+        assert_eq!(light.incidence(Point3f { x: 0.0, y: 10.0, z: 0.0 }).unwrap().1, 2.0);
+    }
+
+    #[test]
+    fn spot_light_fades_at_cone_edge() {
+        let light = Light::spot(Point3f { x: 0.0, y: 1.0, z: 0.0 }, Vec3f { x: 0.0, y: -1.0, z: 0.0 },
+                                45.0f32.to_radians(), 20.0f32.to_radians());
+        let at_angle = |deg: f32| {
+            let rad = deg.to_radians();
+            light.incidence(Point3f { x: rad.tan(), y: 0.0, z: 0.0 }).map(|(_, factor)| factor)
+        };
+        assert_eq!(at_angle(0.0), Some(1.0));
+        assert_eq!(at_angle(20.0), Some(1.0));
+        let edge = at_angle(35.0).unwrap();
+        assert!(edge > 0.0 && edge < 1.0, "{}", edge);
+        assert_eq!(at_angle(50.0), None);
+    }
+}
@@ -1,7 +1,9 @@
-use crate::tgaimage::{TGAColor, TGAImage};
+use crate::framebuffer::Framebuffer;
+use crate::math::Point2f;
+use crate::tgaimage::TGAColor;
 
 #[allow(dead_code)]
-pub fn draw_line_naive(mut x0: i32, mut y0: i32, mut x1: i32, mut y1: i32, image: &mut TGAImage, color: TGAColor) {
+pub fn draw_line_naive<F: Framebuffer>(mut x0: i32, mut y0: i32, mut x1: i32, mut y1: i32, image: &mut F, color: TGAColor) {
     let mut steep = false;
     if (x1 - x0).abs() < (y1 - y0).abs() {
         std::mem::swap(&mut x0, &mut y0);
@@ -17,14 +19,16 @@ pub fn draw_line_naive(mut x0: i32, mut y0: i32, mut x1: i32, mut y1: i32, image
     for x in x0..=x1 {
         let y = y0 + (y1 - y0) * (x - x0) / (x1 - x0);
         if steep {
-            image.set(y, x, color).unwrap();
+            image.set_pixel(y, x, color);
         } else {
-            image.set(x, y, color).unwrap();
+            image.set_pixel(x, y, color);
         }
     }
 }
 
-pub fn draw_line_faster(mut x0: i32, mut y0: i32, mut x1: i32, mut y1: i32, image: &mut TGAImage, color: TGAColor) {
+/// Bresenham line without clipping, pixels outside of the image are
+/// skipped one by one.
+pub fn draw_line_faster<F: Framebuffer>(mut x0: i32, mut y0: i32, mut x1: i32, mut y1: i32, image: &mut F, color: TGAColor) {
     let mut steep = false;
     if (x1 - x0).abs() < (y1 - y0).abs() {
         std::mem::swap(&mut x0, &mut y0);
@@ -45,9 +49,9 @@ pub fn draw_line_faster(mut x0: i32, mut y0: i32, mut x1: i32, mut y1: i32, imag
     let mut acc = 0;
     for x in x0..=x1 {
         if steep {
-            image.set(y, x, color).unwrap();
+            image.set_pixel(y, x, color);
         } else {
-            image.set(x, y, color).unwrap();
+            image.set_pixel(x, y, color);
         }
         acc += dacc;
         if acc > dx {
@@ -57,15 +61,312 @@ pub fn draw_line_faster(mut x0: i32, mut y0: i32, mut x1: i32, mut y1: i32, imag
     }
 }
 
-pub fn draw_line (x0: i32, y0: i32, x1: i32, y1: i32, image: &mut TGAImage, color: TGAColor) {
-    // line_naive(x0, y0, x1, y1, image, color);
-    draw_line_faster(x0, y0, x1, y1, image, color);
+/// Parameters along the p0 -> p1 segment of its part within the rectangle
+/// from `min` to `max`, 0 is `p0` and 1 is `p1`. `None` if the segment
+/// misses the rectangle. Uses the Liang-Barsky algorithm, in double
+/// precision so that far away endpoints don't move the clipped line.
+fn clip_params(p0: Point2f, p1: Point2f, min: Point2f, max: Point2f) -> Option<(f64, f64)> {
+    if ![p0.x, p0.y, p1.x, p1.y].iter().all(|c| c.is_finite()) {
+        return None
+    }
+    let (dx, dy) = (p1.x as f64 - p0.x as f64, p1.y as f64 - p0.y as f64);
+    let (mut t0, mut t1) = (0.0f64, 1.0f64);
+    // the segment is inside of each boundary where p * t <= q
+    for (p, q) in [(-dx, p0.x as f64 - min.x as f64),
+                   (dx, max.x as f64 - p0.x as f64),
+                   (-dy, p0.y as f64 - min.y as f64),
+                   (dy, max.y as f64 - p0.y as f64)] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None
+            }
+        } else if p < 0.0 {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+    }
+    (t0 <= t1).then_some((t0, t1))
+}
+
+/// Part of the p0 -> p1 segment within the rectangle from `min` to `max`,
+/// `None` if the segment misses it.
+pub fn clip_line(p0: Point2f, p1: Point2f, min: Point2f, max: Point2f) -> Option<(Point2f, Point2f)> {
+    let (t0, t1) = clip_params(p0, p1, min, max)?;
+    let at = |t: f64| match t {
+        0.0 => p0,
+        1.0 => p1,
+        _ => Point2f {
+            x: (p0.x as f64 + (p1.x as f64 - p0.x as f64) * t) as f32,
+            y: (p0.y as f64 + (p1.y as f64 - p0.y as f64) * t) as f32,
+        },
+    };
+    Some((at(t0), at(t1)))
+}
+
+/// Line between points given in pixels, rounded to the closest pixels.
+/// Endpoints can be anywhere, the line is clipped to the image.
+pub fn draw_line_float<F: Framebuffer>(p0: Point2f, p1: Point2f, image: &mut F, color: TGAColor) {
+    let max = Point2f { x: (image.width() - 1) as f32, y: (image.height() - 1) as f32 };
+    let Some((a, b)) = clip_line(p0, p1, Point2f { x: 0.0, y: 0.0 }, max) else {
+        return
+    };
+    draw_line_faster(a.x.round() as i32, a.y.round() as i32, b.x.round() as i32, b.y.round() as i32, image, color);
+}
+
+/// Line between pixels, endpoints can be outside of the image.
+pub fn draw_line<F: Framebuffer>(x0: i32, y0: i32, x1: i32, y1: i32, image: &mut F, color: TGAColor) {
+    let to_point = |x: i32, y: i32| Point2f { x: x as f32, y: y as f32 };
+    draw_line_float(to_point(x0, y0), to_point(x1, y1), image, color);
+}
+
+/// Blend `color` into the pixel with `coverage` in [0, 1] as its weight.
+fn blend_pixel<F: Framebuffer>(x: i32, y: i32, image: &mut F, color: TGAColor, coverage: f32) {
+    if coverage <= 0.0 {
+        return;
+    }
+    let blended = match image.get_pixel(x, y) {
+        Some(background) => background.lerp(color, coverage),
+        None => color.scale(coverage),
+    };
+    image.set_pixel(x, y, blended);
+}
+
+/// Anti-aliased line with Xiaolin Wu's algorithm, blended with the pixels
+/// of the image. Pixel centers are at integer coordinates, as with
+/// [`draw_line`]. The line is clipped to the image.
+pub fn draw_line_aa<F: Framebuffer>(p0: Point2f, p1: Point2f, image: &mut F, color: TGAColor) {
+    // the clipped endpoints land on the pixels just outside of the image
+    let min = Point2f { x: -1.0, y: -1.0 };
+    let max = Point2f { x: image.width() as f32, y: image.height() as f32 };
+    let Some((p0, p1)) = clip_line(p0, p1, min, max) else {
+        return
+    };
+    let (mut x0, mut y0, mut x1, mut y1) = (p0.x, p0.y, p1.x, p1.y);
+    let steep = (y1 - y0).abs() > (x1 - x0).abs();
+    if steep {
+        std::mem::swap(&mut x0, &mut y0);
+        std::mem::swap(&mut x1, &mut y1);
+    }
+    if x1 < x0 {
+        std::mem::swap(&mut x0, &mut x1);
+        std::mem::swap(&mut y0, &mut y1);
+    }
+
+    let mut plot = |x: i32, y: i32, coverage: f32| {
+        if steep {
+            blend_pixel(y, x, image, color, coverage);
+        } else {
+            blend_pixel(x, y, image, color, coverage);
+        }
+    };
+
+    let dx = x1 - x0;
+    let gradient = if dx == 0.0 { 0.0 } else { (y1 - y0) / dx };
+
+    // endpoints cover the part of their pixel column the line spans
+    let mut endpoint = |x: f32, y: f32, gap: f32| -> i32 {
+        let x_end = x.round();
+        let y_end = y + gradient * (x_end - x);
+        let (px, py) = (x_end as i32, y_end.floor() as i32);
+        plot(px, py, (1.0 - y_end.fract_pos()) * gap);
+        plot(px, py + 1, y_end.fract_pos() * gap);
+        px
+    };
+    let first = endpoint(x0, y0, 1.0 - (x0 + 0.5).fract_pos());
+    let last = endpoint(x1, y1, (x1 + 0.5).fract_pos());
+    if last <= first {
+        return;
+    }
+
+    let mut y = y0 + gradient * (first as f32 + 1.0 - x0);
+    for x in first + 1..last {
+        let py = y.floor() as i32;
+        plot(x, py, 1.0 - y.fract_pos());
+        plot(x, py + 1, y.fract_pos());
+        y += gradient;
+    }
+}
+
+/// Fractional part counted from the floor, also for negative numbers.
+trait FractPos {
+    fn fract_pos(self) -> Self;
+}
+
+impl FractPos for f32 {
+    fn fract_pos(self) -> f32 {
+        self - self.floor()
+    }
+}
+
+/// Shape of the ends of thick lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineCap {
+    /// The line ends exactly at its endpoints.
+    Butt,
+    /// The line is extended by half of its width past the endpoints.
+    Square,
+    /// Half circles around the endpoints.
+    Round,
+}
+
+/// How [`draw_line_styled`] draws a line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineStyle {
+    /// Width in pixels.
+    pub width: f32,
+    pub cap: LineCap,
+    /// Lengths of the alternating dashes and gaps in pixels starting with a
+    /// dash, empty for a solid line. A list of odd length is repeated twice
+    /// to make the pattern.
+    pub dashes: Vec<f32>,
+    /// Distance into the dash pattern at the start of the line.
+    pub dash_offset: f32,
+    /// Whether the line edges are blended with the image.
+    pub anti_aliased: bool,
+}
+
+impl Default for LineStyle {
+    fn default() -> Self {
+        LineStyle {
+            width: 1.0,
+            cap: LineCap::Butt,
+            dashes: vec![],
+            dash_offset: 0.0,
+            anti_aliased: false,
+        }
+    }
+}
+
+/// Dash patterns repeating over less than this many pixels are drawn as
+/// solid lines.
+const MIN_DASH_PATTERN: f32 = 1.0 / 16.0;
+
+/// Parts of the p0 -> p1 line drawn with the dash pattern, as distances
+/// along the line. Only the parts within the `visible` range of distances
+/// are returned, cut to it.
+fn dash_ranges(length: f32, dashes: &[f32], offset: f32, visible: (f32, f32)) -> Vec<(f32, f32)> {
+    let (lo, hi) = visible;
+    let total: f32 = dashes.iter().sum();
+    if dashes.is_empty() || dashes.iter().any(|&d| d < 0.0) || total.is_nan() || total < MIN_DASH_PATTERN {
+        return vec![visible];
+    }
+    let pattern = if dashes.len() % 2 == 1 { dashes.repeat(2) } else { dashes.to_vec() };
+    let total = total * (pattern.len() / dashes.len()) as f32;
+
+    let mut ranges = vec![];
+    let mut start = 0.0 - offset.rem_euclid(total);
+    // skip the whole repetitions of the pattern before the visible part
+    start += ((lo - start) / total).floor().max(0.0) * total;
+    // the lengths may be lost to rounding far along the line, bound the
+    // repetitions to the ones covering the visible part
+    let repetitions = ((hi - start) / total).max(0.0).ceil() as usize + 1;
+    for (i, &len) in pattern.iter().enumerate().cycle().take(repetitions * pattern.len()) {
+        if start > hi || (start == length && length > 0.0) {
+            break;
+        }
+        let end = start + len;
+        if i % 2 == 0 && end >= lo {
+            ranges.push((start.max(lo), end.min(hi)));
+        }
+        start = end;
+    }
+    ranges
+}
+
+/// Signed distance from the point to the outline of a thick segment going
+/// from the origin along the X axis for `length`, negative inside.
+fn segment_distance(u: f32, v: f32, length: f32, half_width: f32, cap: LineCap) -> f32 {
+    match cap {
+        LineCap::Round => {
+            let t = u.clamp(0.0, length);
+            (u - t).hypot(v) - half_width
+        },
+        LineCap::Butt | LineCap::Square => {
+            let ext = if cap == LineCap::Square { half_width } else { 0.0 };
+            let qx = (u - length * 0.5).abs() - (length * 0.5 + ext);
+            let qy = v.abs() - half_width;
+            qx.max(0.0).hypot(qy.max(0.0)) + qx.max(qy).min(0.0)
+        },
+    }
+}
+
+/// Thick line segment, pixels are covered by the distance of their centers
+/// to the outline of the segment.
+fn draw_thick_segment<F: Framebuffer>(p0: Point2f, p1: Point2f, style: &LineStyle, image: &mut F, color: TGAColor) {
+    let (dx, dy) = (p1.x - p0.x, p1.y - p0.y);
+    let length = dx.hypot(dy);
+    if length == 0.0 && style.cap == LineCap::Butt {
+        return;
+    }
+    let (dir_x, dir_y) = if length > 0.0 { (dx / length, dy / length) } else { (1.0, 0.0) };
+    let half_width = style.width.max(0.0) * 0.5;
+
+    let reach = half_width * std::f32::consts::SQRT_2 + 1.0;
+    let clamp_x = |x: f32| x.clamp(-1.0, image.width() as f32) as i32;
+    let clamp_y = |y: f32| y.clamp(-1.0, image.height() as f32) as i32;
+    let (min_x, max_x) = (clamp_x((p0.x.min(p1.x) - reach).floor()), clamp_x((p0.x.max(p1.x) + reach).ceil()));
+    let (min_y, max_y) = (clamp_y((p0.y.min(p1.y) - reach).floor()), clamp_y((p0.y.max(p1.y) + reach).ceil()));
+
+    for y in min_y.max(0)..=max_y.min(image.height() - 1) {
+        for x in min_x.max(0)..=max_x.min(image.width() - 1) {
+            let coverage = if style.anti_aliased {
+                let (px, py) = (x as f32 - p0.x, y as f32 - p0.y);
+                let dist = segment_distance(px * dir_x + py * dir_y, py * dir_x - px * dir_y, length, half_width, style.cap);
+                (0.5 - dist).clamp(0.0, 1.0)
+            } else {
+                // nudging the pixel center breaks the ties of the centers
+                // exactly on the outline, so that the line covers as many
+                // pixels across as its width
+                let (px, py) = (x as f32 + 1.0 / 256.0 - p0.x, y as f32 + 1.0 / 256.0 - p0.y);
+                let dist = segment_distance(px * dir_x + py * dir_y, py * dir_x - px * dir_y, length, half_width, style.cap);
+                if dist < 0.0 { 1.0 } else { 0.0 }
+            };
+            blend_pixel(x, y, image, color, coverage);
+        }
+    }
+}
+
+/// Line with the width, caps and dash pattern of `style`. One pixel wide
+/// lines without caps fall back to [`draw_line_aa`] or [`draw_line_float`].
+/// The line is clipped to the image.
+pub fn draw_line_styled<F: Framebuffer>(p0: Point2f, p1: Point2f, style: &LineStyle, image: &mut F, color: TGAColor) {
+    // parts of the line farther from the image than the line width can't
+    // touch it
+    let reach = style.width.max(0.0) * std::f32::consts::SQRT_2 + 1.0;
+    let min = Point2f { x: -reach, y: -reach };
+    let max = Point2f { x: image.width() as f32 + reach, y: image.height() as f32 + reach };
+    let Some((t0, t1)) = clip_params(p0, p1, min, max) else {
+        return
+    };
+
+    let (dx, dy) = (p1.x - p0.x, p1.y - p0.y);
+    let length = dx.hypot(dy);
+    let at = |t: f32| {
+        let t = if length > 0.0 { t / length } else { 0.0 };
+        Point2f { x: p0.x + dx * t, y: p0.y + dy * t }
+    };
+
+    let visible = ((t0 * length as f64) as f32, (t1 * length as f64) as f32);
+    for (start, end) in dash_ranges(length, &style.dashes, style.dash_offset, visible) {
+        let (a, b) = (at(start), at(end));
+        if style.width <= 1.0 && style.cap == LineCap::Butt {
+            if style.anti_aliased {
+                draw_line_aa(a, b, image, color);
+            } else {
+                draw_line_float(a, b, image, color);
+            }
+        } else {
+            draw_thick_segment(a, b, style, image, color);
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::math::Point2f;
     use crate::tgaimage::{tga_format, TGAImage, TGAColor};
-    use super::draw_line;
+    use super::{clip_line, dash_ranges, draw_line, draw_line_aa, draw_line_styled, LineCap, LineStyle};
 
     fn setup_1_image() -> (TGAImage, TGAColor) {
         (TGAImage::with_size(6, 6, tga_format::RGB),
@@ -127,4 +428,157 @@ mod test {
             }
         }
     }
+
+    fn pnt(x: f32, y: f32) -> Point2f {
+        Point2f { x, y }
+    }
+
+    /// Coordinates of the pixels that are not black.
+    fn drawn_pixels(img: &TGAImage) -> Vec<(i32, i32)> {
+        let black = TGAColor::from_rgb(0, 0, 0);
+        (0..img.height).flat_map(|y| (0..img.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| img.get(x, y).unwrap() != black)
+            .collect()
+    }
+
+    #[test]
+    fn aa_line_splits_intensity_between_rows() {
+        let mut img = TGAImage::with_size(8, 6, tga_format::RGB);
+        draw_line_aa(pnt(1.0, 2.25), pnt(6.0, 2.25), &mut img, TGAColor::from_rgb(200, 200, 200));
+
+        for x in 2..6 {
+            assert_eq!(img.get(x, 2).unwrap().r(), 150, "@ {}", x);
+            assert_eq!(img.get(x, 3).unwrap().r(), 50, "@ {}", x);
+        }
+        // endpoints at pixel centers cover half of their pixels
+        assert_eq!(img.get(1, 2).unwrap().r(), 75);
+        assert_eq!(img.get(0, 2).unwrap().r(), 0);
+        assert_eq!(img.get(7, 2).unwrap().r(), 0);
+    }
+
+    #[test]
+    fn aa_line_blends_with_image() {
+        let mut img = TGAImage::with_size(6, 6, tga_format::RGB);
+        for x in 0..6 {
+            img.set(x, 3, TGAColor::from_rgb(0, 0, 200)).unwrap();
+        }
+        draw_line_aa(pnt(2.0, 0.0), pnt(2.0, 5.0), &mut img, TGAColor::from_rgb(200, 0, 0));
+        assert_eq!(img.get(2, 3).unwrap(), TGAColor::from_rgb(200, 0, 0));
+        draw_line_aa(pnt(0.0, 2.5), pnt(5.0, 2.5), &mut img, TGAColor::from_rgb(0, 200, 0));
+        assert_eq!(img.get(3, 3).unwrap(), TGAColor::from_rgb(0, 100, 100));
+    }
+
+    #[test]
+    fn aa_line_is_symmetric() {
+        let (mut img1, mut img2, col) = setup_2_images();
+        draw_line_aa(pnt(0.3, 1.2), pnt(5.4, 4.1), &mut img1, col);
+        draw_line_aa(pnt(5.4, 4.1), pnt(0.3, 1.2), &mut img2, col);
+        assert_eq!(img1, img2);
+    }
+
+    #[test]
+    fn thick_line_covers_its_width() {
+        let col = TGAColor::from_rgb(255, 255, 255);
+        let draw = |width: f32, cap: LineCap| {
+            let mut img = TGAImage::with_size(12, 12, tga_format::RGB);
+            let style = LineStyle { width, cap, ..LineStyle::default() };
+            draw_line_styled(pnt(3.0, 6.0), pnt(8.0, 6.0), &style, &mut img, col);
+            drawn_pixels(&img)
+        };
+
+        let butt = draw(3.0, LineCap::Butt);
+        assert_eq!(butt.len(), 5 * 3);
+        assert!(butt.iter().all(|&(x, y)| (3..8).contains(&x) && (5..8).contains(&y)));
+        let even = draw(2.0, LineCap::Butt);
+        assert_eq!(even.len(), 5 * 2);
+
+        let square = draw(4.0, LineCap::Square);
+        assert_eq!(square.len(), 9 * 4);
+        assert!(square.contains(&(1, 4)) && square.contains(&(9, 7)));
+
+        // round caps bulge past the endpoints but not into the corners
+        let round = draw(5.0, LineCap::Round);
+        assert!(round.contains(&(1, 6)) && round.contains(&(10, 6)));
+        assert!(!round.contains(&(1, 4)) && !round.contains(&(10, 8)));
+    }
+
+    #[test]
+    fn thick_aa_line_has_soft_edges() {
+        let mut img = TGAImage::with_size(12, 12, tga_format::RGB);
+        let style = LineStyle { width: 3.0, anti_aliased: true, ..LineStyle::default() };
+        draw_line_styled(pnt(1.0, 2.0), pnt(10.0, 9.0), &style, &mut img, TGAColor::from_rgb(255, 255, 255));
+        let values: Vec<u8> = (0..12).flat_map(|y| (0..12).map(move |x| (x, y)))
+            .map(|(x, y)| img.get(x, y).unwrap().r())
+            .collect();
+        assert!(values.contains(&255));
+        assert!(values.iter().any(|&v| v > 0 && v < 255));
+    }
+
+    #[test]
+    fn dash_pattern() {
+        let all = (0.0, 10.0);
+        assert_eq!(dash_ranges(10.0, &[], 0.0, all), [(0.0, 10.0)]);
+        assert_eq!(dash_ranges(10.0, &[3.0, 2.0], 0.0, all), [(0.0, 3.0), (5.0, 8.0)]);
+        assert_eq!(dash_ranges(10.0, &[3.0, 2.0], 4.0, all), [(1.0, 4.0), (6.0, 9.0)]);
+        // odd patterns alternate between dashes and gaps
+        assert_eq!(dash_ranges(10.0, &[2.0], 0.0, all), [(0.0, 2.0), (4.0, 6.0), (8.0, 10.0)]);
+        // the pattern keeps its phase when only a part of the line is visible
+        assert_eq!(dash_ranges(1e9, &[3.0, 2.0], 0.0, (1e6 + 2.0, 1e6 + 7.0)), [(1e6 + 2.0, 1e6 + 3.0), (1e6 + 5.0, 1e6 + 7.0)]);
+        // patterns too fine to see are solid, and lengths lost to rounding
+        // don't keep the loop going
+        assert_eq!(dash_ranges(150.0, &[1e-9, 1e-9], 0.0, (0.0, 150.0)), [(0.0, 150.0)]);
+        assert!(dash_ranges(1e9, &[1e-9, 1.0], 0.0, (1e8, 1e8 + 150.0)).len() < 200);
+
+        let mut img = TGAImage::with_size(12, 4, tga_format::RGB);
+        let style = LineStyle { width: 2.0, dashes: vec![3.0, 2.0], ..LineStyle::default() };
+        draw_line_styled(pnt(0.0, 2.0), pnt(10.0, 2.0), &style, &mut img, TGAColor::from_rgb(255, 255, 255));
+        let columns: Vec<i32> = drawn_pixels(&img).iter().filter(|p| p.1 == 2).map(|p| p.0).collect();
+        assert_eq!(columns, [0, 1, 2, 5, 6, 7]);
+
+        let mut img = TGAImage::with_size(160, 4, tga_format::RGB);
+        let style = LineStyle { dashes: vec![1e-9, 1e-9], ..LineStyle::default() };
+        draw_line_styled(pnt(0.0, 2.0), pnt(150.0, 2.0), &style, &mut img, TGAColor::from_rgb(255, 255, 255));
+        assert_eq!(drawn_pixels(&img).len(), 151);
+    }
+
+    #[test]
+    fn clip_line_to_rectangle() {
+        let (min, max) = (pnt(0.0, 0.0), pnt(10.0, 5.0));
+        let inside = (pnt(1.0, 1.0), pnt(9.0, 4.0));
+        let clipped = clip_line(inside.0, inside.1, min, max).unwrap();
+        assert_eq!((clipped.0.x, clipped.0.y, clipped.1.x, clipped.1.y), (1.0, 1.0, 9.0, 4.0));
+
+        let (a, b) = clip_line(pnt(-10.0, -5.0), pnt(20.0, 10.0), min, max).unwrap();
+        assert_eq!((a.x, a.y, b.x, b.y), (0.0, 0.0, 10.0, 5.0));
+        let (a, b) = clip_line(pnt(5.0, 2.0), pnt(5.0, 100.0), min, max).unwrap();
+        assert_eq!((a.x, a.y, b.x, b.y), (5.0, 2.0, 5.0, 5.0));
+
+        assert!(clip_line(pnt(-1.0, 1.0), pnt(-1.0, 4.0), min, max).is_none());
+        assert!(clip_line(pnt(-5.0, 4.0), pnt(4.0, 13.0), min, max).is_none());
+        assert!(clip_line(pnt(f32::NAN, 4.0), pnt(4.0, 3.0), min, max).is_none());
+    }
+
+    #[test]
+    fn off_screen_endpoints_are_clipped() {
+        let (mut img, col) = setup_1_image();
+        draw_line(-1_000_000_000, 3, 1_000_000_000, 3, &mut img, col);
+        assert_eq!(drawn_pixels(&img), (0..6).map(|x| (x, 3)).collect::<Vec<_>>());
+
+        // the clipped line follows the original one instead of the border
+        img.clear();
+        draw_line(-6, -3, 14, 7, &mut img, col);
+        let drawn = drawn_pixels(&img);
+        assert_eq!(drawn.len(), 6);
+        assert!(drawn.iter().all(|&(x, y)| (y as f32 - x as f32 * 0.5).abs() <= 0.5), "{:?}", drawn);
+
+        img.clear();
+        draw_line(-10, 20, 20, 30, &mut img, col);
+        assert!(drawn_pixels(&img).is_empty());
+
+        img.clear();
+        draw_line_aa(pnt(-1e9, -2e9), pnt(1e9, 2e9), &mut img, col);
+        assert!(!drawn_pixels(&img).is_empty());
+        let style = LineStyle { width: 3.0, cap: LineCap::Round, dashes: vec![2.0, 1.0], ..LineStyle::default() };
+        draw_line_styled(pnt(-1e7, 3.0), pnt(1e7, 3.0), &style, &mut img, col);
+    }
 }
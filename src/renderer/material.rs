@@ -0,0 +1,37 @@
+use super::{BlendMode, Sampler, Texture};
+
+/// Surface appearance of a textured mesh.
+pub struct Material<'a> {
+    pub diffuse: &'a Texture,
+    /// Tangent space normal map, e.g. the `_nm_tangent.tga` textures.
+    pub normal_map: Option<&'a Texture>,
+    /// Specular intensity map, e.g. the `_spec.tga` textures. Without it
+    /// the surface has no specular highlights.
+    pub specular_map: Option<&'a Texture>,
+    /// Blinn-Phong exponent of the specular highlights.
+    pub shininess: f32,
+    /// Filtering and wrapping of all the textures of the material.
+    pub sampler: Sampler,
+    /// Blending of the diffuse color with what is already drawn, use the
+    /// alpha of the diffuse texture for transparent surfaces.
+    pub blend: BlendMode,
+    /// Whether the surface hides what is drawn after it, usually disabled
+    /// for transparent surfaces drawn over the opaque geometry.
+    pub depth_write: bool,
+}
+
+impl<'a> Material<'a> {
+    pub const DEFAULT_SHININESS: f32 = 32.0;
+
+    pub fn new(diffuse: &'a Texture) -> Material<'a> {
+        Material {
+            diffuse,
+            normal_map: None,
+            specular_map: None,
+            shininess: Self::DEFAULT_SHININESS,
+            sampler: Sampler::default(),
+            blend: BlendMode::Replace,
+            depth_write: true,
+        }
+    }
+}
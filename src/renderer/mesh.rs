@@ -1,37 +1,40 @@
-use crate::math::{Point2f, Point3f};
 use crate::mesh::IndexedTriangleMesh;
-use crate::tgaimage::{TGAColor, TGAImage};
-use super::{Context, draw_3d_triangle, draw_3d_triangle_textured};
+use crate::framebuffer::Framebuffer;
+use crate::tgaimage::TGAColor;
+use super::{Context, DepthBuffer, FlatShader, Material, Shader, TexturedShader};
+use super::tiles::draw_mesh_tiled;
 
-pub fn draw_mesh(mesh: &IndexedTriangleMesh,
-                 ctx: &Context,
-                 image: &mut TGAImage,
-                 color: TGAColor) {
-    let mut z_buf = vec![f32::MAX; (image.width * image.height) as usize];
-    for tri in &mesh.triangles {
-        let mut vs = [Point3f::origin(); 3];
-        for (i, v_idx) in tri.vertices.iter().enumerate() {
-            vs[i] = mesh.vertices[(*v_idx - 1) as usize];
-        }
-        draw_3d_triangle(vs[0], vs[1], vs[2], ctx, image, color, &mut z_buf);
-    }
+/// Draw the mesh running `shader` stages for each of its triangles.
+pub fn draw_mesh_with_shader<F, S>(mesh: &IndexedTriangleMesh,
+                                   ctx: &Context,
+                                   shader: &S,
+                                   image: &mut F) where F: Framebuffer, S: Shader {
+    let mut depth_buf = DepthBuffer::with_samples(image.width(), image.height(), ctx.anti_aliasing.samples());
+    draw_mesh_with_depth(mesh, ctx, shader, image, &mut depth_buf);
 }
 
-pub fn draw_mesh_textured(mesh: &IndexedTriangleMesh,
-                          ctx: &Context,
-                          image: &mut TGAImage,
-                          diff_texture: &TGAImage) {
-    let mut z_buf = vec![f32::MAX; (image.width * image.height) as usize];
-    for tri in &mesh.triangles {
-        let mut vs = [Point3f::origin(); 3];
-        let mut tcs = [Point2f::origin(); 3];
-        for (i, v_idx) in tri.vertices.iter().enumerate() {
-            vs[i] = mesh.vertices[(*v_idx - 1) as usize];
-        }
-        assert!(mesh.texcoords.is_some(), "draw_mesh_textured called for model without texture coords");
-        for (i, tc_idx) in tri.texcoords.as_ref().unwrap().iter().enumerate() {
-            tcs[i] = mesh.texcoords.as_ref().unwrap()[(*tc_idx - 1) as usize];
-        }
-        draw_3d_triangle_textured(vs[0], vs[1], vs[2], tcs[0], tcs[1], tcs[2], ctx, image, diff_texture, &mut z_buf);
-    }
+/// Same as [`draw_mesh_with_shader`] but depth tested against and written
+/// to `depth_buf`, which should have the same size as the image and as many
+/// samples per pixel as the anti-aliasing of `ctx` uses.
+pub fn draw_mesh_with_depth<F, S>(mesh: &IndexedTriangleMesh,
+                                  ctx: &Context,
+                                  shader: &S,
+                                  image: &mut F,
+                                  depth_buf: &mut DepthBuffer) where F: Framebuffer, S: Shader {
+    draw_mesh_tiled(mesh, ctx, shader, image, depth_buf);
+}
+
+pub fn draw_mesh<F: Framebuffer>(mesh: &IndexedTriangleMesh,
+                                 ctx: &Context,
+                                 image: &mut F,
+                                 color: TGAColor) {
+    draw_mesh_with_shader(mesh, ctx, &FlatShader { ctx, color }, image);
+}
+
+pub fn draw_mesh_textured<F: Framebuffer>(mesh: &IndexedTriangleMesh,
+                                          ctx: &Context,
+                                          image: &mut F,
+                                          material: &Material) {
+    assert!(mesh.texcoords.is_some(), "draw_mesh_textured called for model without texture coords");
+    draw_mesh_with_shader(mesh, ctx, &TexturedShader { ctx, material }, image);
 }
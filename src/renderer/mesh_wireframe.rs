@@ -1,46 +1,329 @@
-use crate::math::{BndBox2f, Point2f};
+use std::collections::HashMap;
+
+use crate::math::{Point2f, Point3f, Vec3f};
+use crate::math::hvec::HVec4f;
 use crate::mesh::IndexedTriangleMesh;
-use crate::tgaimage::{TGAColor, TGAImage};
-use super::{Camera, draw_line};
-
-pub fn draw_mesh_wireframe(model: &IndexedTriangleMesh,
-                           camera: &Camera,
-                           image: &mut TGAImage,
-                           color: TGAColor) {
-    let iw = (image.width - 1) as f32;
-    let ih = (image.height - 1) as f32;
-    let img_half_dims = Point2f { x: 0.5 * iw, y: 0.5 * ih };
-    let mut screen_bbox = BndBox2f::new_empty();
-    screen_bbox.add_point(Point2f { x: -iw * 0.5, y: -ih * 0.5 });
-    screen_bbox.add_point(Point2f { x: iw * 0.5, y: ih * 0.5 });
-
-    // Compute 2D camera plane coords of triangle edges
-    let mut pnt2s: Vec<Point2f> = Vec::new();
-    for tri in model.triangles.as_slice() {
-        let vert1_iter = tri.vertices.into_iter();
-        let vert2_iter = tri.vertices.into_iter();
-        for (v1, v2) in vert1_iter.zip(vert2_iter.cycle().skip(1)) {
-            let p1 = model.vertices[v1 as usize - 1];
-            let p2 = model.vertices[v2 as usize - 1];
-
-            let pnt2_1 = camera.project(&p1) * img_half_dims;
-            let pnt2_2 = camera.project(&p2) * img_half_dims;
-            pnt2s.push(screen_bbox.clamp(pnt2_1));
-            pnt2s.push(screen_bbox.clamp(pnt2_2));
+use crate::framebuffer::{Framebuffer, NullFramebuffer};
+use crate::tgaimage::TGAColor;
+use super::{draw_mesh_with_depth, Camera, Context, CullMode, DepthBuffer, Fragment, Projection, Shader};
+use super::line::{clip_line, draw_line_float};
+use super::clip::clip_segment;
+use super::triangle::to_pixel;
+
+/// Edges of the mesh drawn by [`draw_mesh_wireframe_with`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum WireframeEdges {
+    /// Every triangle edge.
+    #[default]
+    All,
+    /// Silhouette edges between triangles turned towards and away from the
+    /// camera, crease edges between triangles whose normals differ by more
+    /// than `crease_angle` (in radians) and boundary edges of the mesh.
+    Feature { crease_angle: f32 },
+}
+
+/// Depth offset of the mesh surface the lines are tested against, keeps the
+/// edges lying on the surface from fighting with it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PolygonOffset {
+    /// Multiplier of the surface depth slope, the change of depth to the
+    /// neighbouring pixels.
+    pub factor: f32,
+    /// Constant offset in normalized device depth.
+    pub units: f32,
+}
+
+impl Default for PolygonOffset {
+    fn default() -> Self {
+        PolygonOffset { factor: 1.5, units: 1e-5 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WireframeOptions {
+    /// Hide the parts of the edges behind the mesh surface, which is
+    /// rendered into a depth buffer first.
+    pub hidden_lines: bool,
+    pub edges: WireframeEdges,
+    /// Color of the mesh surface drawn under the lines, implies hidden
+    /// lines.
+    pub fill: Option<TGAColor>,
+    pub polygon_offset: PolygonOffset,
+}
+
+/// Draw every triangle edge of the mesh.
+pub fn draw_mesh_wireframe<F: Framebuffer>(model: &IndexedTriangleMesh,
+                                           camera: &Camera,
+                                           image: &mut F,
+                                           color: TGAColor) {
+    draw_mesh_wireframe_with(model, camera, &WireframeOptions::default(), image, color);
+}
+
+/// Draw the edges of the mesh selected by `options`.
+pub fn draw_mesh_wireframe_with<F: Framebuffer>(model: &IndexedTriangleMesh,
+                                                camera: &Camera,
+                                                options: &WireframeOptions,
+                                                image: &mut F,
+                                                color: TGAColor) {
+    let depth = (options.hidden_lines || options.fill.is_some())
+        .then(|| surface_depth(model, camera, options.fill, image));
+
+    let (w, h) = (image.width(), image.height());
+    // pixel centers are at integer coordinates for line drawing
+    let to_screen = |p: HVec4f| {
+        let p = p.to_point3();
+        let pixel = to_pixel(p.drop_z(), w, h);
+        Point3f { x: pixel.x - 0.5, y: pixel.y - 0.5, z: p.z }
+    };
+
+    let view_proj = camera.view_projection_matrix();
+    for [v1, v2] in mesh_edges(model, camera, options.edges) {
+        let p1 = model.vertices[v1 as usize - 1];
+        let p2 = model.vertices[v2 as usize - 1];
+
+        // cut off the parts of the edge outside the camera depth range
+        let clipped = clip_segment(view_proj * HVec4f::from(p1), view_proj * HVec4f::from(p2));
+        let Some((clip_p1, clip_p2)) = clipped else {
+            continue;
+        };
+        let (a, b) = (to_screen(clip_p1), to_screen(clip_p2));
+        match &depth {
+            Some(depth) => draw_depth_tested_line(a, b, depth, options.polygon_offset, image, color),
+            None => draw_line_float(a.drop_z(), b.drop_z(), image, color),
+        }
+    }
+}
+
+/// Vertex index pairs of the edges to draw, each edge once.
+fn mesh_edges(model: &IndexedTriangleMesh, camera: &Camera, edges: WireframeEdges) -> Vec<[u32; 2]> {
+    // edges in the order of the triangles with the triangles sharing them
+    let mut order: Vec<[u32; 2]> = vec![];
+    let mut faces: HashMap<[u32; 2], Vec<usize>> = HashMap::new();
+    for (face, tri) in model.triangles.iter().enumerate() {
+        for nth in 0..3 {
+            let (a, b) = (tri.vertices[nth], tri.vertices[(nth + 1) % 3]);
+            let key = [a.min(b), a.max(b)];
+            let adjacent = faces.entry(key).or_insert_with(|| {
+                order.push(key);
+                vec![]
+            });
+            adjacent.push(face);
         }
     }
 
-    // Draw the edges
-    for i in (0..pnt2s.len()).step_by(2) {
-        let (p1, p2) = (pnt2s[i], pnt2s[i + 1]);
-        draw_line(
-            (iw * 0.5 + p1.x) as i32,
-            (ih * 0.5 + p1.y) as i32,
-            (iw * 0.5 + p2.x) as i32,
-            (ih * 0.5 + p2.y) as i32,
-            image,
-            color,
-        );
+    let WireframeEdges::Feature { crease_angle } = edges else {
+        return order
+    };
+    let front_facing = |face: usize| {
+        let to_camera = match camera.projection() {
+            Projection::Perspective { .. } => Vec3f::from(camera.location()) - Vec3f::from(model.vertex(face, 0)),
+            Projection::Orthographic { .. } => camera.direction() * -1.0,
+        };
+        model.face_normal(face).dot(to_camera) > 0.0
+    };
+    let min_cos = crease_angle.cos();
+    order.into_iter().filter(|key| {
+        let &[f1, f2] = faces[key].as_slice() else {
+            // boundary or non-manifold edge
+            return true
+        };
+        front_facing(f1) != front_facing(f2) || model.face_normal(f1).dot(model.face_normal(f2)) < min_cos
+    }).collect()
+}
+
+/// Shader of the surface the lines are depth tested against.
+struct SurfaceShader<'a> {
+    camera: &'a Camera,
+    fill: TGAColor,
+}
+
+impl Shader for SurfaceShader<'_> {
+    type Varyings = ();
+
+    fn vertex(&self, mesh: &IndexedTriangleMesh, face: usize, nth: usize) -> (HVec4f, ()) {
+        (self.camera.view_projection_matrix() * HVec4f::from(mesh.vertex(face, nth)), ())
+    }
+
+    fn fragment(&self, _frag: &Fragment<()>) -> Option<TGAColor> {
+        Some(self.fill)
+    }
+}
+
+/// Depth of the mesh surface, also drawn into the image with the `fill`
+/// color if set.
+fn surface_depth<F: Framebuffer>(model: &IndexedTriangleMesh,
+                                 camera: &Camera,
+                                 fill: Option<TGAColor>,
+                                 image: &mut F) -> DepthBuffer {
+    // back faces seen through the holes of open meshes hide lines too
+    let mut ctx = Context::new(camera.clone(), camera.direction());
+    ctx.cull_mode = CullMode::None;
+    let mut depth = DepthBuffer::with_size(image.width(), image.height());
+    let shader = SurfaceShader { camera, fill: fill.unwrap_or(TGAColor::from_rgb(255, 255, 255)) };
+    if fill.is_some() {
+        draw_mesh_with_depth(model, &ctx, &shader, image, &mut depth);
+    } else {
+        let mut null = NullFramebuffer { width: image.width(), height: image.height() };
+        draw_mesh_with_depth(model, &ctx, &shader, &mut null, &mut depth);
+    }
+    depth
+}
+
+/// Whether a line point at `z` is in front of the offset surface depth.
+fn in_front(depth: &DepthBuffer, x: i32, y: i32, z: f32, offset: PolygonOffset) -> bool {
+    let Some(stored) = depth.get(x, y) else {
+        return false
+    };
+    if stored == DepthBuffer::EMPTY {
+        return true
+    }
+    // smaller of the differences to both sides, so that the pixels on the
+    // edges of the surface don't take the slope from the background
+    let diff = |nx: i32, ny: i32| depth.get(nx, ny)
+        .filter(|&d| d != DepthBuffer::EMPTY)
+        .map(|d| (d - stored).abs());
+    let slope = |a: Option<f32>, b: Option<f32>| match (a, b) {
+        (Some(a), Some(b)) => a.min(b),
+        (a, b) => a.or(b).unwrap_or(0.0),
+    };
+    let slope_x = slope(diff(x - 1, y), diff(x + 1, y));
+    let slope_y = slope(diff(x, y - 1), diff(x, y + 1));
+    z <= stored + offset.units + offset.factor * slope_x.max(slope_y)
+}
+
+/// Line between points with pixel X and Y and normalized device depth,
+/// drawn where it is in front of the surface `depth`.
+fn draw_depth_tested_line<F: Framebuffer>(a: Point3f,
+                                          b: Point3f,
+                                          depth: &DepthBuffer,
+                                          offset: PolygonOffset,
+                                          image: &mut F,
+                                          color: TGAColor) {
+    let max = Point2f { x: (image.width() - 1) as f32, y: (image.height() - 1) as f32 };
+    let Some((p0, p1)) = clip_line(a.drop_z(), b.drop_z(), Point2f { x: 0.0, y: 0.0 }, max) else {
+        return
+    };
+    // depth is linear along the line in the screen space
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let len2 = dx * dx + dy * dy;
+    let depth_at = |p: Point2f| {
+        let t = if len2 > 0.0 { ((p.x - a.x) * dx + (p.y - a.y) * dy) / len2 } else { 0.0 };
+        a.z + (b.z - a.z) * t
+    };
+    let (z0, z1) = (depth_at(p0), depth_at(p1));
+
+    let steps = (p1.x - p0.x).abs().max((p1.y - p0.y).abs()).round() as i32;
+    for i in 0..=steps {
+        let t = if steps > 0 { i as f32 / steps as f32 } else { 0.0 };
+        let x = (p0.x + (p1.x - p0.x) * t).round() as i32;
+        let y = (p0.y + (p1.y - p0.y) * t).round() as i32;
+        if in_front(depth, x, y, z0 + (z1 - z0) * t, offset) {
+            image.set_pixel(x, y, color);
+        }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::{draw_mesh_wireframe, draw_mesh_wireframe_with, WireframeEdges, WireframeOptions};
+    use crate::math::{Point3f, Vec3f};
+    use crate::mesh::{IndexedTriangleMesh, Triangle};
+    use crate::renderer::Camera;
+    use crate::tgaimage::{tga_format, TGAColor, TGAImage};
+
+    fn white() -> TGAColor {
+        TGAColor::from_rgb(255, 255, 255)
+    }
+
+    /// Camera looking down the Z axis at the [-1, 1] square.
+    fn camera() -> Camera {
+        Camera::orthographic(Point3f { x: 0.0, y: 0.0, z: 5.0 },
+                             Vec3f { x: 0.0, y: 0.0, z: -1.0 },
+                             Vec3f { x: 0.0, y: 1.0, z: 0.0 },
+                             1.0, 1.0, 10.0)
+    }
+
+    /// Square facing the camera at depth `z`, split along the diagonal
+    /// going up or the one going down.
+    fn square(mesh: &mut IndexedTriangleMesh, half: f32, z: f32, diagonal_up: bool) {
+        let first = mesh.vertices.len() as u32 + 1;
+        mesh.vertices.extend([
+            Point3f { x: -half, y: -half, z },
+            Point3f { x: half, y: -half, z },
+            Point3f { x: half, y: half, z },
+            Point3f { x: -half, y: half, z },
+        ]);
+        let split = if diagonal_up { [[0, 1, 2], [0, 2, 3]] } else { [[0, 1, 3], [1, 2, 3]] };
+        for [a, b, c] in split {
+            mesh.triangles.push(Triangle { vertices: [first + a, first + b, first + c], texcoords: None, normals: None });
+        }
+    }
+
+    /// Small square in front of the middle of a large one, their edges are
+    /// on pixel centers of a 40x40 image: 3 and 36 for the large one, 11
+    /// and 28 for the small one.
+    fn two_squares() -> IndexedTriangleMesh {
+        let mut mesh = IndexedTriangleMesh { vertices: vec![], triangles: vec![], texcoords: None, normals: None, tangents: None };
+        square(&mut mesh, 0.825, 0.0, true);
+        square(&mut mesh, 0.425, 1.0, false);
+        mesh
+    }
+
+    fn is_white(image: &TGAImage, x: i32, y: i32) -> bool {
+        image.get(x, y).unwrap() == white()
+    }
+
+    #[test]
+    fn hidden_lines_are_removed() {
+        let mesh = two_squares();
+        let mut all = TGAImage::with_size(40, 40, tga_format::RGB);
+        draw_mesh_wireframe(&mesh, &camera(), &mut all, white());
+        let mut hidden = TGAImage::with_size(40, 40, tga_format::RGB);
+        let options = WireframeOptions { hidden_lines: true, ..WireframeOptions::default() };
+        draw_mesh_wireframe_with(&mesh, &camera(), &options, &mut hidden, white());
+        hidden.write_to_file("assets/test_wireframe_hidden.tga").unwrap();
+
+        // the diagonal of the large square behind the small one
+        assert!(is_white(&all, 18, 18));
+        assert!(!is_white(&hidden, 18, 18));
+        assert!(is_white(&hidden, 8, 8) && is_white(&hidden, 31, 31));
+        // the diagonal of the small square and the outer edges stay
+        assert!(is_white(&hidden, 20, 19));
+        assert!(is_white(&hidden, 3, 20) && is_white(&hidden, 20, 36));
+        assert!((11..=28).all(|i| is_white(&hidden, 11, i) && is_white(&hidden, i, 28)));
+    }
+
+    #[test]
+    fn feature_edges_skip_flat_diagonals() {
+        let mesh = two_squares();
+        let mut image = TGAImage::with_size(40, 40, tga_format::RGB);
+        let options = WireframeOptions {
+            hidden_lines: true,
+            edges: WireframeEdges::Feature { crease_angle: 30.0f32.to_radians() },
+            ..WireframeOptions::default()
+        };
+        draw_mesh_wireframe_with(&mesh, &camera(), &options, &mut image, white());
+
+        assert!(!is_white(&image, 20, 19) && !is_white(&image, 8, 8));
+        assert!(is_white(&image, 3, 20) && is_white(&image, 11, 20));
+    }
+
+    #[test]
+    fn filled_overlay_keeps_edges_on_the_surface() {
+        let mesh = two_squares();
+        let fill = TGAColor::from_rgb(90, 90, 90);
+        let mut image = TGAImage::with_size(40, 40, tga_format::RGB);
+        let options = WireframeOptions { fill: Some(fill), ..WireframeOptions::default() };
+        draw_mesh_wireframe_with(&mesh, &camera(), &options, &mut image, white());
+        image.write_to_file("assets/test_wireframe_overlay.tga").unwrap();
+
+        assert_eq!(image.get(22, 14).unwrap(), fill);
+        assert_eq!(image.get(0, 0).unwrap(), TGAColor::from_rgb(0, 0, 0));
+        // every pixel of the edges of the front square is drawn
+        for i in 11..=28 {
+            assert!(is_white(&image, 11, i) && is_white(&image, 28, i), "{}", i);
+            assert!(is_white(&image, i, 11) && is_white(&image, i, 28), "{}", i);
+            assert!(is_white(&image, i, 39 - i), "{}", i);
+        }
+    }
+}
@@ -0,0 +1,506 @@
+use crate::math::{Point2f, Vec2f, Vec3f};
+use crate::math::hvec::HVec4f;
+use crate::mesh::{IndexedTriangleMesh, TangentBasis};
+use crate::tgaimage::TGAColor;
+use super::{BlendMode, Context, Material, Projection, ShadingMode, Texture};
+
+/// Per-vertex values passed from the vertex to the fragment stage of a
+/// shader, interpolated across the triangle.
+pub trait Varyings: Copy + Send + Sync {
+    /// Weighted sum of the values at the triangle vertices, weights are
+    /// barycentric coordinates of the point within the triangle.
+    fn interpolate(vs: &[Self; 3], bary: Vec3f) -> Self;
+}
+
+impl Varyings for () {
+    fn interpolate(_: &[Self; 3], _: Vec3f) -> Self {}
+}
+
+impl Varyings for f32 {
+    fn interpolate(vs: &[Self; 3], bary: Vec3f) -> Self {
+        vs[0] * bary.x + vs[1] * bary.y + vs[2] * bary.z
+    }
+}
+
+impl Varyings for Vec2f {
+    fn interpolate(vs: &[Self; 3], bary: Vec3f) -> Self {
+        vs[0] * bary.x + vs[1] * bary.y + vs[2] * bary.z
+    }
+}
+
+impl Varyings for Point2f {
+    fn interpolate(vs: &[Self; 3], bary: Vec3f) -> Self {
+        let vs = vs.map(Vec2f::from);
+        Point2f::origin() + Vec2f::interpolate(&vs, bary)
+    }
+}
+
+impl Varyings for Vec3f {
+    fn interpolate(vs: &[Self; 3], bary: Vec3f) -> Self {
+        vs[0] * bary.x + vs[1] * bary.y + vs[2] * bary.z
+    }
+}
+
+impl<const N: usize> Varyings for [f32; N] {
+    fn interpolate(vs: &[Self; 3], bary: Vec3f) -> Self {
+        let mut res = [0.0f32; N];
+        for (i, v) in res.iter_mut().enumerate() {
+            *v = vs[0][i] * bary.x + vs[1][i] * bary.y + vs[2][i] * bary.z;
+        }
+        res
+    }
+}
+
+macro_rules! impl_varyings_for_tuple {
+    ($($name:ident : $idx:tt),+) => {
+        impl<$($name: Varyings),+> Varyings for ($($name,)+) {
+            fn interpolate(vs: &[Self; 3], bary: Vec3f) -> Self {
+                ($($name::interpolate(&[vs[0].$idx, vs[1].$idx, vs[2].$idx], bary),)+)
+            }
+        }
+    };
+}
+
+impl_varyings_for_tuple!(A: 0, B: 1);
+impl_varyings_for_tuple!(A: 0, B: 1, C: 2);
+impl_varyings_for_tuple!(A: 0, B: 1, C: 2, D: 3);
+
+/// Input of the fragment stage.
+#[derive(Clone, Copy, Debug)]
+pub struct Fragment<V> {
+    /// Pixel coordinates of the fragment.
+    pub x: i32,
+    pub y: i32,
+    /// Depth of the fragment in [-1, 1], increasing away from the camera.
+    pub depth: f32,
+    pub varyings: V,
+    /// Change of the varyings to the next pixel along X and Y, e.g. for
+    /// texture filtering.
+    pub ddx: V,
+    pub ddy: V,
+}
+
+/// Programmable stages of the triangle pipeline. Stages run in parallel
+/// for different triangles and pixels.
+pub trait Shader: Sync {
+    type Varyings: Varyings;
+
+    /// Vertex stage: clip space position and varyings of the `nth` vertex of
+    /// triangle `face` of the mesh.
+    fn vertex(&self, mesh: &IndexedTriangleMesh, face: usize, nth: usize) -> (HVec4f, Self::Varyings);
+
+    /// Fragment stage: color of the fragment or `None` to discard it.
+    fn fragment(&self, frag: &Fragment<Self::Varyings>) -> Option<TGAColor>;
+
+    /// How the fragment colors are combined with the render target, the
+    /// triangles of blended meshes are drawn back to front.
+    fn blend_mode(&self) -> BlendMode {
+        BlendMode::Replace
+    }
+
+    /// Whether the fragments store their depth, otherwise they are only
+    /// depth tested.
+    fn depth_write(&self) -> bool {
+        true
+    }
+}
+
+/// Light reaching a surface point as red, green and blue factors. The
+/// surface color is scaled by `diffuse`, which includes the ambient light,
+/// and `specular` highlights are added on top of it.
+#[derive(Clone, Copy, Debug)]
+struct SurfaceLight {
+    diffuse: Vec3f,
+    specular: Vec3f,
+}
+
+/// Unit direction from the world space point to the camera.
+fn view_direction(ctx: &Context, position: Vec3f) -> Vec3f {
+    let camera = &ctx.camera;
+    match camera.projection() {
+        Projection::Perspective { .. } => (Vec3f::from(camera.location()) - position).normalize(),
+        Projection::Orthographic { .. } => camera.direction() * -1.0,
+    }
+}
+
+/// Ambient light plus the light of all the context lights reaching the
+/// point with unit `normal`. Blinn-Phong highlights are only computed if
+/// `shininess` is given.
+fn light_surface(ctx: &Context, normal: Vec3f, position: Vec3f, shininess: Option<f32>) -> SurfaceLight {
+    let zero = Vec3f { x: 0.0, y: 0.0, z: 0.0 };
+    let ambient = Vec3f { x: ctx.ambient, y: ctx.ambient, z: ctx.ambient };
+    let mut res = SurfaceLight { diffuse: ambient, specular: zero };
+    for light in &ctx.lights {
+        let Some((direction, factor)) = light.incidence(position.into()) else {
+            continue
+        };
+        // no light and no highlights on the surfaces turned away from it
+        let intensity = -normal.dot(direction);
+        if intensity <= 0.0 {
+            continue
+        }
+        let shadow = light.shadow().map_or(1.0, |shadow| shadow.light_factor(position.into()));
+        let color = light.color_factors() * (factor * shadow);
+        res.diffuse = res.diffuse + color * intensity;
+
+        if let Some(shininess) = shininess {
+            let half_dir = view_direction(ctx, position) - direction;
+            if half_dir.norm() > 1e-6 {
+                res.specular = res.specular + color * normal.dot(half_dir.normalize()).max(0.0).powf(shininess);
+            }
+        }
+    }
+    res
+}
+
+fn modulate(color: TGAColor, light: Vec3f) -> TGAColor {
+    color.modulate(light.x, light.y, light.z)
+}
+
+/// Lighting inputs shared by the built-in shaders.
+#[derive(Clone, Copy, Debug)]
+pub struct LightVaryings {
+    /// Diffuse light at the vertex for Gouraud shading, RGB.
+    pub diffuse: Vec3f,
+    /// Unit normal at the vertex, the triangle normal for flat shading.
+    pub normal: Vec3f,
+    /// World space position, used for point lights and shadow lookups.
+    pub position: Vec3f,
+}
+
+impl Varyings for LightVaryings {
+    fn interpolate(vs: &[Self; 3], bary: Vec3f) -> Self {
+        LightVaryings {
+            diffuse: Vec3f::interpolate(&vs.map(|v| v.diffuse), bary),
+            normal: Vec3f::interpolate(&vs.map(|v| v.normal), bary),
+            position: Vec3f::interpolate(&vs.map(|v| v.position), bary),
+        }
+    }
+}
+
+impl LightVaryings {
+    fn at_vertex(ctx: &Context, mesh: &IndexedTriangleMesh, face: usize, nth: usize) -> Self {
+        let face_normal = mesh.face_normal(face);
+        let normal = if ctx.shading == ShadingMode::Flat {
+            face_normal
+        } else {
+            mesh.normal(face, nth).map_or(face_normal, |n| n.normalize())
+        };
+        let position = mesh.vertex(face, nth).into();
+        let diffuse = if ctx.shading == ShadingMode::Gouraud {
+            light_surface(ctx, normal, position, None).diffuse
+        } else {
+            Vec3f { x: 0.0, y: 0.0, z: 0.0 }
+        };
+        LightVaryings { diffuse, normal, position }
+    }
+
+    /// Diffuse light at the fragment according to the shading mode.
+    fn fragment_diffuse(&self, ctx: &Context) -> Vec3f {
+        match ctx.shading {
+            ShadingMode::Gouraud => self.diffuse,
+            ShadingMode::Flat | ShadingMode::Phong => {
+                light_surface(ctx, self.normal.normalize(), self.position, None).diffuse
+            },
+        }
+    }
+}
+
+/// Single color shading lit according to the context shading mode.
+pub struct FlatShader<'a> {
+    pub ctx: &'a Context,
+    pub color: TGAColor,
+}
+
+impl Shader for FlatShader<'_> {
+    type Varyings = LightVaryings;
+
+    fn vertex(&self, mesh: &IndexedTriangleMesh, face: usize, nth: usize) -> (HVec4f, LightVaryings) {
+        let pos = self.ctx.camera.view_projection_matrix() * HVec4f::from(mesh.vertex(face, nth));
+        (pos, LightVaryings::at_vertex(self.ctx, mesh, face, nth))
+    }
+
+    fn fragment(&self, frag: &Fragment<LightVaryings>) -> Option<TGAColor> {
+        Some(modulate(self.color, frag.varyings.fragment_diffuse(self.ctx)))
+    }
+}
+
+/// Varyings of the textured shader.
+#[derive(Clone, Copy, Debug)]
+pub struct MaterialVaryings {
+    pub light: LightVaryings,
+    pub texcoord: Point2f,
+    pub tangent: Vec3f,
+    pub bitangent: Vec3f,
+}
+
+impl Varyings for MaterialVaryings {
+    fn interpolate(vs: &[Self; 3], bary: Vec3f) -> Self {
+        MaterialVaryings {
+            light: LightVaryings::interpolate(&vs.map(|v| v.light), bary),
+            texcoord: Point2f::interpolate(&vs.map(|v| v.texcoord), bary),
+            tangent: Vec3f::interpolate(&vs.map(|v| v.tangent), bary),
+            bitangent: Vec3f::interpolate(&vs.map(|v| v.bitangent), bary),
+        }
+    }
+}
+
+/// Material shading lit according to the context shading mode. Normal
+/// and specular maps of the material switch lighting to per pixel.
+pub struct TexturedShader<'a> {
+    pub ctx: &'a Context,
+    pub material: &'a Material<'a>,
+}
+
+impl TexturedShader<'_> {
+    fn sample(&self, texture: &Texture, frag: &Fragment<MaterialVaryings>) -> TGAColor {
+        let uv = frag.varyings.texcoord;
+        let duv_dx = Vec2f::from(frag.ddx.texcoord);
+        let duv_dy = Vec2f::from(frag.ddy.texcoord);
+        self.material.sampler.sample_grad(texture, uv, duv_dx, duv_dy)
+    }
+
+    /// Shading normal perturbed by the normal map of the material.
+    fn mapped_normal(&self, normal_map: &Texture, frag: &Fragment<MaterialVaryings>) -> Vec3f {
+        let v = &frag.varyings;
+        let normal = v.light.normal.normalize();
+        let Some(tangent) = v.tangent.orthogonalize(normal) else {
+            return normal
+        };
+        let tangent = tangent.normalize();
+        let mut bitangent = normal.cross(tangent);
+        if bitangent.dot(v.bitangent) < 0.0 {
+            bitangent = bitangent * -1.0;
+        }
+
+        let texel = self.sample(normal_map, frag);
+        let to_coord = |c: u8| c as f32 / 255.0 * 2.0 - 1.0;
+        let mapped = tangent * to_coord(texel.r()) +
+                     bitangent * to_coord(texel.g()) +
+                     normal * to_coord(texel.b());
+        if mapped.norm() > 1e-6 { mapped.normalize() } else { normal }
+    }
+}
+
+impl Shader for TexturedShader<'_> {
+    type Varyings = MaterialVaryings;
+
+    fn vertex(&self, mesh: &IndexedTriangleMesh, face: usize, nth: usize) -> (HVec4f, MaterialVaryings) {
+        let vertex = mesh.vertex(face, nth);
+        let pos = self.ctx.camera.view_projection_matrix() * HVec4f::from(vertex);
+        let texcoord = mesh.texcoord(face, nth)
+                           .expect("TexturedShader used for model without texture coords");
+        let zero = Vec3f { x: 0.0, y: 0.0, z: 0.0 };
+        let basis = mesh.tangent(face, nth)
+                        .or_else(|| mesh.face_tangent(face))
+                        .unwrap_or(TangentBasis { tangent: zero, bitangent: zero });
+        (pos, MaterialVaryings {
+            light: LightVaryings::at_vertex(self.ctx, mesh, face, nth),
+            texcoord,
+            tangent: basis.tangent,
+            bitangent: basis.bitangent,
+        })
+    }
+
+    fn fragment(&self, frag: &Fragment<MaterialVaryings>) -> Option<TGAColor> {
+        let v = &frag.varyings;
+        let texel = self.sample(self.material.diffuse, frag);
+        if self.material.normal_map.is_none() && self.material.specular_map.is_none() {
+            return Some(modulate(texel, v.light.fragment_diffuse(self.ctx)))
+        }
+
+        let normal = match self.material.normal_map {
+            Some(normal_map) => self.mapped_normal(normal_map, frag),
+            None => v.light.normal.normalize(),
+        };
+        let Some(specular_map) = self.material.specular_map else {
+            return Some(modulate(texel, light_surface(self.ctx, normal, v.light.position, None).diffuse))
+        };
+        let light = light_surface(self.ctx, normal, v.light.position, Some(self.material.shininess));
+        // grayscale maps keep the value in the first (blue) component
+        let strength = self.sample(specular_map, frag).b() as f32 / 255.0;
+        let highlight = modulate(TGAColor::from_rgb(255, 255, 255), light.specular * strength);
+        Some(modulate(texel, light.diffuse).add_scaled(highlight, 1.0))
+    }
+
+    fn blend_mode(&self) -> BlendMode {
+        self.material.blend
+    }
+
+    fn depth_write(&self) -> bool {
+        self.material.depth_write
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FlatShader, Varyings};
+    use crate::math::{Point2f, Point3f, Vec3f};
+    use crate::mesh::{IndexedTriangleMesh, Triangle};
+    use crate::renderer::{draw_mesh_textured, draw_mesh_with_shader, Attenuation, Camera, Context, Light, Material,
+                          ShadingMode, Texture};
+    use crate::tgaimage::{tga_format, TGAColor, TGAImage};
+
+    fn camera() -> Camera {
+        Camera::orthographic(Point3f { x: 0.0, y: 0.0, z: 5.0 },
+                             Vec3f { x: 0.0, y: 0.0, z: -1.0 },
+                             Vec3f { x: 0.0, y: 1.0, z: 0.0 },
+                             1.0, 1.0, 10.0)
+    }
+
+    /// Triangle facing the camera.
+    fn triangle() -> IndexedTriangleMesh {
+        IndexedTriangleMesh {
+            vertices: vec![Point3f { x: -1.0, y: -1.0, z: 0.0 },
+                           Point3f { x: 1.0, y: -1.0, z: 0.0 },
+                           Point3f { x: 0.0, y: 1.0, z: 0.0 }],
+            triangles: vec![Triangle { vertices: [1, 2, 3], texcoords: None, normals: None }],
+            texcoords: None,
+            normals: None,
+            tangents: None,
+        }
+    }
+
+    /// Pixels inside `triangle()` drawn at 8x8.
+    const INSIDE: [(i32, i32); 5] = [(1, 0), (4, 0), (6, 0), (4, 3), (4, 6)];
+
+    fn shade(mesh: &IndexedTriangleMesh, ctx: &mut Context, shading: ShadingMode) -> TGAImage {
+        ctx.shading = shading;
+        let mut image = TGAImage::with_size(8, 8, tga_format::RGB);
+        draw_mesh_with_shader(mesh, ctx, &FlatShader { ctx, color: TGAColor::from_rgb(200, 200, 200) }, &mut image);
+        image
+    }
+
+    fn inside_colors(image: &TGAImage) -> Vec<TGAColor> {
+        INSIDE.iter().map(|&(x, y)| image.get(x, y).unwrap()).collect()
+    }
+
+    #[test]
+    fn shading_modes() {
+        // normals tilted away from the center of the triangle
+        let mut mesh = triangle();
+        mesh.normals = Some(vec![Vec3f { x: -0.5, y: -0.5, z: 1.0 },
+                                 Vec3f { x: 0.5, y: -0.5, z: 1.0 },
+                                 Vec3f { x: 0.0, y: 0.5, z: 1.0 }]);
+        mesh.triangles[0].normals = Some([1, 2, 3]);
+        let mut ctx = Context::new(camera(), Vec3f { x: 1.0, y: 0.0, z: -1.0 });
+        ctx.ambient = 0.0;
+
+        let flat = inside_colors(&shade(&mesh, &mut ctx, ShadingMode::Flat));
+        assert!(flat.iter().all(|&c| c == flat[0]), "{:?}", flat);
+        for shading in [ShadingMode::Gouraud, ShadingMode::Phong] {
+            let colors = inside_colors(&shade(&mesh, &mut ctx, shading));
+            // facing the light on the left, away from it on the right
+            assert!(colors[0].r() > colors[2].r() + 50, "{:?} {:?}", shading, colors);
+        }
+
+        // the per pixel normal catches the light of a close point light
+        // that doesn't reach the vertices
+        ctx.lights = vec![Light::point(Point3f { x: 0.0, y: -0.3, z: 0.3 })];
+        let gouraud = shade(&mesh, &mut ctx, ShadingMode::Gouraud).get(4, 2).unwrap().r();
+        let phong = shade(&mesh, &mut ctx, ShadingMode::Phong).get(4, 2).unwrap().r();
+        assert!(phong > gouraud + 100, "{} {}", phong, gouraud);
+
+        // without vertex normals all modes use the triangle normal
+        ctx.lights = vec![Light::directional(Vec3f { x: 1.0, y: 0.0, z: -1.0 })];
+        let flat = shade(&triangle(), &mut ctx, ShadingMode::Flat);
+        assert_eq!(shade(&triangle(), &mut ctx, ShadingMode::Gouraud), flat);
+        assert_eq!(shade(&triangle(), &mut ctx, ShadingMode::Phong), flat);
+    }
+
+    fn uniform_texture(color: TGAColor) -> Texture {
+        let mut image = TGAImage::with_size(2, 2, tga_format::RGB);
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            image.set(x, y, color).unwrap();
+        }
+        Texture::new(image)
+    }
+
+    #[test]
+    fn normal_and_specular_maps() {
+        // texture coordinates along X and Y, the tangent basis is the world one
+        let mut mesh = triangle();
+        mesh.texcoords = Some(vec![Point2f { x: 0.0, y: 0.0 }, Point2f { x: 1.0, y: 0.0 }, Point2f { x: 0.5, y: 1.0 }]);
+        mesh.triangles[0].texcoords = Some([1, 2, 3]);
+        let mut ctx = Context::new(camera(), Vec3f { x: 1.0, y: 0.0, z: -1.0 });
+        ctx.ambient = 0.0;
+        ctx.shading = ShadingMode::Phong;
+
+        let diffuse = uniform_texture(TGAColor::from_rgb(100, 100, 100));
+        // tangent space normal tilted towards -X, i.e. towards the light
+        let normal_map = uniform_texture(TGAColor::from_rgb(64, 128, 238));
+        let specular_map = uniform_texture(TGAColor::from_rgb(255, 255, 255));
+        let render = |material: &Material| {
+            let mut image = TGAImage::with_size(8, 8, tga_format::RGB);
+            draw_mesh_textured(&mesh, &ctx, &mut image, material);
+            image.get(4, 3).unwrap().r()
+        };
+
+        let mut material = Material::new(&diffuse);
+        let plain = render(&material);
+        assert_eq!(plain, 70);
+
+        material.normal_map = Some(&normal_map);
+        let mapped = render(&material);
+        assert!(mapped > plain + 20, "{} {}", mapped, plain);
+
+        material.normal_map = None;
+        material.specular_map = Some(&specular_map);
+        material.shininess = 4.0;
+        let highlighted = render(&material);
+        assert!(highlighted > plain + 100, "{} {}", highlighted, plain);
+    }
+
+    #[test]
+    fn interpolate_tuple_varyings() {
+        let vs = [
+            (1.0f32, Point2f { x: 0.0, y: 0.0 }, [0.0f32, 3.0]),
+            (2.0f32, Point2f { x: 1.0, y: 0.0 }, [3.0f32, 0.0]),
+            (3.0f32, Point2f { x: 0.0, y: 1.0 }, [6.0f32, 3.0]),
+        ];
+        let (a, p, arr) = Varyings::interpolate(&vs, Vec3f { x: 0.5, y: 0.25, z: 0.25 });
+        assert_eq!(a, 1.75);
+        assert_eq!((p.x, p.y), (0.25, 0.25));
+        assert_eq!(arr, [2.25, 2.25]);
+    }
+
+    #[test]
+    fn faces_turned_away_from_light_get_ambient_light() {
+        let mesh = triangle();
+        // lit from behind
+        let mut ctx = Context::new(camera(), Vec3f { x: 0.0, y: 0.0, z: 1.0 });
+        ctx.ambient = 0.2;
+        let mut image = TGAImage::with_size(8, 8, tga_format::RGB);
+        draw_mesh_with_shader(&mesh, &ctx, &FlatShader { ctx: &ctx, color: TGAColor::from_rgb(200, 100, 50) }, &mut image);
+        assert_eq!(image.get(4, 3).unwrap(), TGAColor::from_rgb(40, 20, 10));
+
+        ctx.lights = vec![Light::directional(Vec3f { x: 0.0, y: 0.0, z: -1.0 })];
+        draw_mesh_with_shader(&mesh, &ctx, &FlatShader { ctx: &ctx, color: TGAColor::from_rgb(200, 100, 50) }, &mut image);
+        assert_eq!(image.get(4, 3).unwrap(), TGAColor::from_rgb(240, 120, 60));
+    }
+
+    #[test]
+    fn colored_lights_add_up() {
+        let mut ctx = Context::new(camera(), Vec3f { x: 0.0, y: 0.0, z: -1.0 });
+        ctx.ambient = 0.0;
+        ctx.lights[0].color = TGAColor::from_rgb(255, 0, 0);
+        ctx.lights[0].intensity = 0.5;
+        // straight above the triangle, lights it fully at its top vertex
+        let mut point = Light::point(Point3f { x: 0.0, y: 1.0, z: 1.0 });
+        point.color = TGAColor::from_rgb(0, 0, 255);
+        point.attenuation = Attenuation::INVERSE_SQUARE;
+        ctx.lights.push(point);
+        // turned away from the triangle
+        ctx.lights.push(Light::directional(Vec3f { x: 0.0, y: 0.0, z: 1.0 }));
+
+        let mut image = TGAImage::with_size(8, 8, tga_format::RGB);
+        draw_mesh_with_shader(&triangle(), &ctx, &FlatShader { ctx: &ctx, color: TGAColor::from_rgb(200, 100, 200) }, &mut image);
+        let top = image.get(4, 6).unwrap();
+        assert_eq!(top.r(), 100);
+        assert_eq!(top.g(), 0);
+        assert!(top.b() > 150, "{:?}", top);
+        let bottom = image.get(1, 0).unwrap();
+        assert_eq!(bottom.r(), 100);
+        assert!(bottom.b() < top.b() / 2, "{:?}", bottom);
+    }
+}
@@ -0,0 +1,195 @@
+use crate::framebuffer::NullFramebuffer;
+use crate::math::{BndBox3f, Point3f, Vec3f};
+use crate::math::hvec::HVec4f;
+use crate::mesh::IndexedTriangleMesh;
+use crate::tgaimage::{TGAColor, TGAImage};
+use super::{draw_mesh_with_depth, Camera, Context, CullMode, DepthBuffer, Fragment, Shader};
+use super::triangle::to_pixel;
+
+/// Depth of the scene as seen from a directional light, used to find out
+/// which points are lit and which ones are in the shadow.
+///
+/// The map is rendered with an orthographic camera looking along the light
+/// direction and covering a bounding sphere of the shadow casters.
+#[derive(Clone, Debug)]
+pub struct ShadowMap {
+    camera: Camera,
+    depth: DepthBuffer,
+    /// Depth offset in world units subtracted from the depth of the tested
+    /// points, prevents surfaces from shadowing themselves ("shadow acne").
+    pub bias: f32,
+    /// Radius in texels of the percentage closer filtering kernel, 0 gives
+    /// hard shadow edges.
+    pub pcf_radius: i32,
+    /// How much light is taken away in the shadow, 1 is a fully black shadow.
+    pub strength: f32,
+}
+
+/// Depth only shader of the light pass.
+struct DepthShader<'a> {
+    camera: &'a Camera,
+}
+
+impl Shader for DepthShader<'_> {
+    type Varyings = ();
+
+    fn vertex(&self, mesh: &IndexedTriangleMesh, face: usize, nth: usize) -> (HVec4f, ()) {
+        (self.camera.view_projection_matrix() * HVec4f::from(mesh.vertex(face, nth)), ())
+    }
+
+    fn fragment(&self, _frag: &Fragment<()>) -> Option<TGAColor> {
+        Some(TGAColor::from_rgb(255, 255, 255))
+    }
+}
+
+impl ShadowMap {
+    pub const DEFAULT_STRENGTH: f32 = 0.7;
+
+    /// Empty `size` x `size` shadow map of the directional `light` for the
+    /// shadow casters within `bounds`.
+    pub(crate) fn new(light: Vec3f, bounds: &BndBox3f, size: i32) -> ShadowMap {
+        let center = bounds.center();
+        let radius = (Vec3f::from(bounds.max) - Vec3f::from(bounds.min)).norm().max(1e-3) * 0.5;
+        let light = light.normalize();
+        let up = if light.y.abs() < 0.99 {
+            Vec3f { x: 0.0, y: 1.0, z: 0.0 }
+        } else {
+            Vec3f { x: 1.0, y: 0.0, z: 0.0 }
+        };
+        let loc = Point3f::from(Vec3f::from(center) - light * (2.0 * radius));
+        let camera = Camera::orthographic(loc, light, up, radius.recip(), radius, 3.0 * radius);
+
+        ShadowMap {
+            camera,
+            depth: DepthBuffer::with_size(size, size),
+            // a couple of texels, texels are 2 * radius / size wide
+            bias: 4.0 * radius / size as f32,
+            pcf_radius: 1,
+            strength: Self::DEFAULT_STRENGTH,
+        }
+    }
+
+    /// Shadow map of `light` with the mesh as the only shadow caster.
+    pub(crate) fn for_mesh(mesh: &IndexedTriangleMesh, light: Vec3f, size: i32) -> ShadowMap {
+        let mut shadow = ShadowMap::new(light, &mesh.bounding_box(), size);
+        shadow.render_mesh(mesh);
+        shadow
+    }
+
+    /// Camera the map is rendered with.
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn depth(&self) -> &DepthBuffer {
+        &self.depth
+    }
+
+    /// Add the mesh to the shadow casters.
+    pub fn render_mesh(&mut self, mesh: &IndexedTriangleMesh) {
+        // both sides of the surfaces cast shadows
+        let mut ctx = Context::new(self.camera.clone(), self.camera.direction());
+        ctx.cull_mode = CullMode::None;
+        let shader = DepthShader { camera: &self.camera };
+        let mut image = NullFramebuffer { width: self.depth.width, height: self.depth.height };
+        draw_mesh_with_depth(mesh, &ctx, &shader, &mut image, &mut self.depth);
+    }
+
+    pub fn clear(&mut self) {
+        self.depth.clear();
+    }
+
+    /// Fraction of the light reaching the world space point, 0 when it is
+    /// fully in the shadow and 1 when it is lit. Points outside of the map
+    /// are lit.
+    pub fn visibility(&self, pnt: Point3f) -> f32 {
+        let pos = (self.camera.view_projection_matrix() * HVec4f::from(pnt)).to_point3();
+        let pixel = to_pixel(pos.drop_z(), self.depth.width, self.depth.height);
+        let (x, y) = (pixel.x.floor() as i32, pixel.y.floor() as i32);
+
+        // depth range of the orthographic camera maps linearly onto [-1, 1]
+        let (near, far) = self.camera.depth_range();
+        let depth = pos.z - self.bias * 2.0 / (far - near);
+
+        let r = self.pcf_radius.max(0);
+        let mut lit = 0;
+        for dy in -r..=r {
+            for dx in -r..=r {
+                if self.depth.get(x + dx, y + dy).is_none_or(|stored| stored >= depth) {
+                    lit += 1;
+                }
+            }
+        }
+        lit as f32 / ((2 * r + 1) * (2 * r + 1)) as f32
+    }
+
+    /// Factor the light reaching the point is scaled by.
+    pub fn light_factor(&self, pnt: Point3f) -> f32 {
+        1.0 - self.strength * (1.0 - self.visibility(pnt))
+    }
+
+    /// Grayscale image of the light space depth for debugging.
+    pub fn depth_image(&self) -> TGAImage {
+        self.depth.to_tga()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::renderer::Light;
+    use crate::math::{Point3f, Vec3f};
+    use crate::mesh::{IndexedTriangleMesh, Triangle};
+
+    fn quad(vertices: &mut Vec<Point3f>, triangles: &mut Vec<Triangle>, half: f32, y: f32) {
+        let first = vertices.len() as u32 + 1;
+        vertices.extend([
+            Point3f { x: -half, y, z: -half },
+            Point3f { x: half, y, z: -half },
+            Point3f { x: half, y, z: half },
+            Point3f { x: -half, y, z: half },
+        ]);
+        for [a, b, c] in [[0, 2, 1], [0, 3, 2]] {
+            triangles.push(Triangle {
+                vertices: [first + a, first + b, first + c],
+                texcoords: None,
+                normals: None,
+            });
+        }
+    }
+
+    fn floor_with_occluder() -> IndexedTriangleMesh {
+        let (mut vertices, mut triangles) = (vec![], vec![]);
+        quad(&mut vertices, &mut triangles, 1.0, 0.0);
+        quad(&mut vertices, &mut triangles, 0.25, 0.5);
+        IndexedTriangleMesh { vertices, triangles, texcoords: None, normals: None, tangents: None }
+    }
+
+    #[test]
+    fn occluder_casts_shadow_on_floor() {
+        let mesh = floor_with_occluder();
+        let mut light = Light::directional_with_shadow(Vec3f { x: 0.0, y: -2.0, z: 0.0 }, &mesh, 64);
+        let shadow = light.shadow_mut().unwrap();
+        shadow.pcf_radius = 0;
+        assert!((shadow.camera().direction() - Vec3f { x: 0.0, y: -1.0, z: 0.0 }).norm() < 1e-6);
+
+        let under = Point3f { x: 0.1, y: 0.0, z: -0.1 };
+        let aside = Point3f { x: 0.7, y: 0.0, z: 0.6 };
+        assert_eq!(shadow.visibility(under), 0.0);
+        assert_eq!(shadow.visibility(aside), 1.0);
+        // the occluder itself is lit
+        assert_eq!(shadow.visibility(Point3f { x: 0.1, y: 0.5, z: -0.1 }), 1.0);
+        // points outside of the map are lit
+        assert_eq!(shadow.visibility(Point3f { x: 5.0, y: 0.0, z: 0.0 }), 1.0);
+    }
+
+    #[test]
+    fn pcf_softens_shadow_edge() {
+        let mesh = floor_with_occluder();
+        let mut light = Light::directional_with_shadow(Vec3f { x: 0.0, y: -1.0, z: 0.0 }, &mesh, 64);
+        let shadow = light.shadow_mut().unwrap();
+        shadow.pcf_radius = 2;
+        let edge = shadow.visibility(Point3f { x: 0.25, y: 0.0, z: 0.0 });
+        assert!(edge > 0.0 && edge < 1.0, "{}", edge);
+        assert_eq!(shadow.light_factor(Point3f { x: 0.0, y: 0.0, z: 0.0 }), 1.0 - shadow.strength);
+    }
+}
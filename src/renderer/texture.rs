@@ -0,0 +1,286 @@
+use crate::math::{Point2f, Vec2f};
+use crate::tgaimage::{TGAColor, TGAImage};
+
+/// Image with a pyramid of downscaled copies (mipmaps) used for filtering
+/// when the texture is minified.
+#[derive(Clone, Debug)]
+pub struct Texture {
+    /// Mip levels from the full size image down to 1x1.
+    levels: Vec<TGAImage>,
+}
+
+impl Texture {
+    /// Texture with the full mipmap pyramid built from `image`.
+    pub fn new(image: TGAImage) -> Texture {
+        let mut levels = vec![image];
+        loop {
+            let last = levels.last().unwrap();
+            if last.width <= 1 && last.height <= 1 {
+                break;
+            }
+            let next = downscale(last);
+            levels.push(next);
+        }
+        Texture { levels }
+    }
+
+    /// Texture with the image only, trilinear filtering of it falls back
+    /// to bilinear.
+    pub fn without_mipmaps(image: TGAImage) -> Texture {
+        Texture { levels: vec![image] }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.levels[0].height
+    }
+
+    /// Mip levels, the first one is the original image.
+    pub fn levels(&self) -> &[TGAImage] {
+        &self.levels
+    }
+}
+
+impl From<TGAImage> for Texture {
+    fn from(image: TGAImage) -> Texture {
+        Texture::new(image)
+    }
+}
+
+fn to_floats(color: TGAColor) -> [f32; 4] {
+    let mut res = [0.0f32; 4];
+    for (res, &c) in res.iter_mut().zip(color.components()) {
+        *res = c as f32;
+    }
+    res
+}
+
+fn from_floats(values: [f32; 4], bytespp: i32) -> TGAColor {
+    let bytes = values.map(|v| v.round().clamp(0.0, 255.0) as u8);
+    TGAColor::from_component_slice(&bytes, bytespp)
+}
+
+fn lerp(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    [0, 1, 2, 3].map(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+/// Half size copy of the image, each pixel is the average of a 2x2 block.
+fn downscale(image: &TGAImage) -> TGAImage {
+    let w = (image.width / 2).max(1);
+    let h = (image.height / 2).max(1);
+    let mut res = TGAImage::with_size(w, h, image.bytespp());
+    for y in 0..h {
+        for x in 0..w {
+            let mut sum = [0.0f32; 4];
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let sx = (2 * x + dx).min(image.width - 1);
+                let sy = (2 * y + dy).min(image.height - 1);
+                let texel = to_floats(image.get(sx, sy).unwrap());
+                for (sum, v) in sum.iter_mut().zip(texel) {
+                    *sum += v * 0.25;
+                }
+            }
+            res.set(x, y, from_floats(sum, image.bytespp())).unwrap();
+        }
+    }
+    res
+}
+
+/// How texels are combined into the sampled color.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    /// Closest texel of the full size image.
+    Nearest,
+    /// Weighted average of the 4 closest texels of the full size image.
+    Bilinear,
+    /// Bilinear samples of the two mip levels closest to the pixel
+    /// footprint blended together.
+    Trilinear,
+}
+
+/// How texture coordinates outside of [0, 1] are mapped onto the texture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WrapMode {
+    /// The texture is tiled.
+    Repeat,
+    /// The texture is tiled with every other tile mirrored.
+    MirroredRepeat,
+    /// Coordinates are clamped to the edge texels.
+    ClampToEdge,
+}
+
+impl WrapMode {
+    /// Texel index within [0, size) for the possibly outside index `i`.
+    fn apply(&self, i: i32, size: i32) -> i32 {
+        match self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::MirroredRepeat => {
+                let i = i.rem_euclid(2 * size);
+                if i < size { i } else { 2 * size - 1 - i }
+            },
+            WrapMode::ClampToEdge => i.clamp(0, size - 1),
+        }
+    }
+}
+
+/// Texture lookup settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sampler {
+    pub filter: Filter,
+    pub wrap_u: WrapMode,
+    pub wrap_v: WrapMode,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Sampler::new(Filter::Trilinear, WrapMode::Repeat)
+    }
+}
+
+impl Sampler {
+    pub fn new(filter: Filter, wrap: WrapMode) -> Sampler {
+        Sampler { filter, wrap_u: wrap, wrap_v: wrap }
+    }
+
+    fn texel(&self, level: &TGAImage, x: i32, y: i32) -> [f32; 4] {
+        let x = self.wrap_u.apply(x, level.width);
+        let y = self.wrap_v.apply(y, level.height);
+        to_floats(level.get(x, y).unwrap())
+    }
+
+    fn nearest(&self, level: &TGAImage, uv: Point2f) -> [f32; 4] {
+        let x = (uv.x * level.width as f32).floor() as i32;
+        let y = (uv.y * level.height as f32).floor() as i32;
+        self.texel(level, x, y)
+    }
+
+    fn bilinear(&self, level: &TGAImage, uv: Point2f) -> [f32; 4] {
+        // texel centers are at half-integer coordinates
+        let x = uv.x * level.width as f32 - 0.5;
+        let y = uv.y * level.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+        let bottom = lerp(self.texel(level, x0, y0), self.texel(level, x0 + 1, y0), tx);
+        let top = lerp(self.texel(level, x0, y0 + 1), self.texel(level, x0 + 1, y0 + 1), tx);
+        lerp(bottom, top, ty)
+    }
+
+    /// Color of the texture at `uv` sampled from the mip level `lod`,
+    /// fractional levels blend two mip levels with trilinear filtering.
+    pub fn sample_lod(&self, texture: &Texture, uv: Point2f, lod: f32) -> TGAColor {
+        let levels = texture.levels();
+        let bytespp = levels[0].bytespp();
+        let values = match self.filter {
+            Filter::Nearest => self.nearest(&levels[0], uv),
+            Filter::Bilinear => self.bilinear(&levels[0], uv),
+            Filter::Trilinear => {
+                let lod = if lod.is_nan() { 0.0 } else { lod.clamp(0.0, (levels.len() - 1) as f32) };
+                let lower = lod.floor() as usize;
+                let upper = (lower + 1).min(levels.len() - 1);
+                lerp(self.bilinear(&levels[lower], uv), self.bilinear(&levels[upper], uv), lod.fract())
+            },
+        };
+        from_floats(values, bytespp)
+    }
+
+    /// Color of the full size texture at `uv`.
+    pub fn sample(&self, texture: &Texture, uv: Point2f) -> TGAColor {
+        self.sample_lod(texture, uv, 0.0)
+    }
+
+    /// Color of the texture at `uv` for a pixel whose texture coordinates
+    /// change by `duv_dx` and `duv_dy` to the neighbouring pixels, which
+    /// selects the mip level.
+    pub fn sample_grad(&self, texture: &Texture, uv: Point2f, duv_dx: Vec2f, duv_dy: Vec2f) -> TGAColor {
+        let size = Vec2f { x: texture.width() as f32, y: texture.height() as f32 };
+        let footprint = |d: Vec2f| ((d.x * size.x).powi(2) + (d.y * size.y).powi(2)).sqrt();
+        let lod = footprint(duv_dx).max(footprint(duv_dy)).max(1e-6).log2();
+        self.sample_lod(texture, uv, lod)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Filter, Sampler, Texture, WrapMode};
+    use crate::math::{Point2f, Vec2f};
+    use crate::tgaimage::{tga_format, TGAColor, TGAImage};
+
+    fn gray(v: u8) -> TGAColor {
+        TGAColor::from_component_slice(&[v], tga_format::GRAYSCALE)
+    }
+
+    /// 4x4 checkerboard of black and white texels.
+    fn checker() -> TGAImage {
+        let mut image = TGAImage::with_size(4, 4, tga_format::GRAYSCALE);
+        for y in 0..4 {
+            for x in 0..4 {
+                image.set(x, y, gray(if (x + y) % 2 == 0 { 0 } else { 200 })).unwrap();
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn wrap_modes() {
+        assert_eq!(WrapMode::Repeat.apply(-1, 4), 3);
+        assert_eq!(WrapMode::Repeat.apply(4, 4), 0);
+        assert_eq!(WrapMode::MirroredRepeat.apply(4, 4), 3);
+        assert_eq!(WrapMode::MirroredRepeat.apply(-1, 4), 0);
+        assert_eq!(WrapMode::MirroredRepeat.apply(9, 4), 1);
+        assert_eq!(WrapMode::ClampToEdge.apply(-5, 4), 0);
+        assert_eq!(WrapMode::ClampToEdge.apply(4, 4), 3);
+    }
+
+    #[test]
+    fn mipmap_pyramid() {
+        let texture = Texture::new(checker());
+        let sizes: Vec<_> = texture.levels().iter().map(|l| (l.width, l.height)).collect();
+        assert_eq!(sizes, [(4, 4), (2, 2), (1, 1)]);
+        assert_eq!(texture.levels()[1].get(1, 0).unwrap(), gray(100));
+        assert_eq!(texture.levels()[2].get(0, 0).unwrap(), gray(100));
+    }
+
+    #[test]
+    fn coordinates_on_the_edge_do_not_panic() {
+        let texture = Texture::new(checker());
+        for filter in [Filter::Nearest, Filter::Bilinear, Filter::Trilinear] {
+            for wrap in [WrapMode::Repeat, WrapMode::MirroredRepeat, WrapMode::ClampToEdge] {
+                let sampler = Sampler::new(filter, wrap);
+                for uv in [(1.0, 1.0), (-0.3, 2.7), (f32::NAN, 0.0)] {
+                    sampler.sample(&texture, Point2f { x: uv.0, y: uv.1 });
+                }
+            }
+        }
+        let clamp = Sampler::new(Filter::Nearest, WrapMode::ClampToEdge);
+        assert_eq!(clamp.sample(&texture, Point2f { x: 1.0, y: 0.0 }), gray(200));
+        let repeat = Sampler::new(Filter::Nearest, WrapMode::Repeat);
+        assert_eq!(repeat.sample(&texture, Point2f { x: 1.0, y: 0.0 }), gray(0));
+    }
+
+    #[test]
+    fn bilinear_blends_neighbours() {
+        let texture = Texture::new(checker());
+        let sampler = Sampler::new(Filter::Bilinear, WrapMode::Repeat);
+        // texel center
+        assert_eq!(sampler.sample(&texture, Point2f { x: 0.375, y: 0.125 }), gray(200));
+        // between two texels
+        assert_eq!(sampler.sample(&texture, Point2f { x: 0.25, y: 0.125 }), gray(100));
+    }
+
+    #[test]
+    fn trilinear_picks_level_by_footprint() {
+        let texture = Texture::new(checker());
+        let sampler = Sampler::default();
+        let uv = Point2f { x: 0.375, y: 0.125 };
+        let zero = Vec2f { x: 0.0, y: 0.0 };
+        let one_texel = Vec2f { x: 0.25, y: 0.0 };
+        let whole = Vec2f { x: 1.0, y: 0.0 };
+        assert_eq!(sampler.sample_grad(&texture, uv, one_texel, zero), gray(200));
+        assert_eq!(sampler.sample_grad(&texture, uv, whole, zero), gray(100));
+        assert_eq!(sampler.sample_lod(&texture, uv, 0.5), gray(150));
+    }
+}
@@ -0,0 +1,489 @@
+use rayon::prelude::*;
+
+use crate::framebuffer::Framebuffer;
+use crate::math::{BndBox2i, Point2i};
+use crate::mesh::IndexedTriangleMesh;
+use crate::tgaimage::TGAColor;
+use super::{AntiAliasing, BlendMode, Context, DepthBuffer, Shader};
+use super::clip::{clip_triangle, ClipVertex};
+use super::triangle::{guard_band, is_culled, rasterize_polygon, to_pixel, RasterTarget};
+
+/// Width and height of the screen tiles in pixels.
+pub const TILE_SIZE: i32 = 32;
+
+/// Triangle after the vertex stage and clipping.
+struct SetupTriangle<V> {
+    polygon: Vec<ClipVertex>,
+    varyings: [V; 3],
+    /// Mean depth of the polygon vertices, for drawing back to front.
+    depth: f32,
+    /// Range of the tiles the triangle overlaps, inclusive.
+    tiles: BndBox2i,
+}
+
+/// Part of the screen rasterized by a single worker, with its own copy of
+/// the colors and depth of the pixel samples.
+struct Tile {
+    size: Point2i,
+    rect: BndBox2i,
+    anti_aliasing: AntiAliasing,
+    blend: BlendMode,
+    depth_write: bool,
+    /// Indices of the triangles overlapping the tile in submission order.
+    triangles: Vec<usize>,
+    depth: Vec<f32>,
+    colors: Vec<Option<TGAColor>>,
+}
+
+impl Tile {
+    fn offset(&self, x: i32, y: i32, sample: usize) -> usize {
+        let pixel = (x - self.rect.min.x + (y - self.rect.min.y) * TILE_SIZE) as usize;
+        pixel * self.anti_aliasing.samples() + sample
+    }
+}
+
+impl RasterTarget for Tile {
+    fn size(&self) -> Point2i {
+        self.size
+    }
+
+    fn rect(&self) -> BndBox2i {
+        self.rect
+    }
+
+    fn anti_aliasing(&self) -> AntiAliasing {
+        self.anti_aliasing
+    }
+
+    fn depth(&self, x: i32, y: i32, sample: usize) -> f32 {
+        self.depth[self.offset(x, y, sample)]
+    }
+
+    fn write(&mut self, x: i32, y: i32, sample: usize, depth: f32, color: TGAColor) {
+        let offset = self.offset(x, y, sample);
+        if self.depth_write {
+            self.depth[offset] = depth;
+        }
+        self.colors[offset] = Some(match self.colors[offset] {
+            Some(dst) => self.blend.blend(color, dst),
+            None => color,
+        });
+    }
+}
+
+/// Run the vertex stage of the triangle, clip and cull it, `None` if
+/// nothing of it is left on the screen.
+fn setup_triangle<S: Shader>(mesh: &IndexedTriangleMesh,
+                             face: usize,
+                             ctx: &Context,
+                             shader: &S,
+                             size: Point2i) -> Option<SetupTriangle<S::Varyings>> {
+    let (p1, v1) = shader.vertex(mesh, face, 0);
+    let (p2, v2) = shader.vertex(mesh, face, 1);
+    let (p3, v3) = shader.vertex(mesh, face, 2);
+    let polygon = clip_triangle(&ClipVertex::triangle([p1, p2, p3]), Some(guard_band(ctx, size)));
+    if polygon.is_empty() || is_culled(&polygon, ctx) {
+        return None
+    }
+
+    let depth = polygon.iter().map(|v| v.pos.to_point3().z).sum::<f32>() / polygon.len() as f32;
+    let mut tiles = BndBox2i::new_empty();
+    for v in &polygon {
+        let pixel = to_pixel(v.pos.to_point3().drop_z(), size.x, size.y);
+        tiles.add_point(Point2i { x: pixel.x.floor() as i32, y: pixel.y.floor() as i32 });
+    }
+    if tiles.max.x < 0 || tiles.max.y < 0 || tiles.min.x >= size.x || tiles.min.y >= size.y {
+        return None
+    }
+    let to_tile = |p: Point2i| Point2i {
+        x: p.x.clamp(0, size.x - 1) / TILE_SIZE,
+        y: p.y.clamp(0, size.y - 1) / TILE_SIZE,
+    };
+    let (min, max) = (to_tile(tiles.min), to_tile(tiles.max));
+    tiles.min = min;
+    tiles.max = max;
+
+    Some(SetupTriangle { polygon, varyings: [v1, v2, v3], depth, tiles })
+}
+
+/// Average color of the pixel samples, samples nothing was drawn to keep
+/// the `background` color.
+fn resolve(samples: &[Option<TGAColor>], background: Option<TGAColor>) -> TGAColor {
+    let drawn = samples.iter().flatten();
+    let bytespp = drawn.clone().map(|c| c.bytespp()).max().unwrap();
+    let background = background.unwrap_or(TGAColor::from_component_slice(&[0; 4], bytespp));
+    let mut sum = [0u32; 4];
+    for color in samples.iter().map(|c| c.unwrap_or(background)) {
+        for (sum, &c) in sum.iter_mut().zip(color.components()) {
+            *sum += c as u32;
+        }
+    }
+    let n = samples.len() as u32;
+    let bytes = sum.map(|s| ((s + n / 2) / n) as u8);
+    TGAColor::from_component_slice(&bytes, bytespp)
+}
+
+/// Draw the mesh with triangles processed in parallel.
+///
+/// The vertex stage runs in parallel over the triangles, which are then
+/// binned into `TILE_SIZE` square tiles of the screen. Tiles are rasterized
+/// in parallel, each one into its own color and depth buffers, which are
+/// copied into `image` and `depth_buf` afterwards. Triangles are drawn in
+/// order within each tile, so the result is the same as of drawing them
+/// one by one.
+///
+/// With anti-aliasing the tiles keep the colors and depth of every sample,
+/// they are stored in `depth_buf` and averaged into the image pixels when
+/// copied.
+///
+/// Blended meshes are drawn back to front sorted by the mean depth of the
+/// triangles, and the tiles start with the colors of the image.
+pub fn draw_mesh_tiled<F, S>(mesh: &IndexedTriangleMesh,
+                             ctx: &Context,
+                             shader: &S,
+                             image: &mut F,
+                             depth_buf: &mut DepthBuffer) where F: Framebuffer, S: Shader {
+    assert_eq!(depth_buf.samples(), ctx.anti_aliasing.samples(),
+               "depth buffer samples don't match the anti-aliasing");
+    let size = Point2i {
+        x: image.width().min(depth_buf.width),
+        y: image.height().min(depth_buf.height),
+    };
+    if size.x <= 0 || size.y <= 0 {
+        return;
+    }
+
+    let mut triangles: Vec<_> = (0..mesh.triangles.len())
+        .into_par_iter()
+        .filter_map(|face| setup_triangle(mesh, face, ctx, shader, size))
+        .collect();
+    let blend = shader.blend_mode();
+    if blend.is_blending() {
+        triangles.par_sort_by(|a, b| b.depth.total_cmp(&a.depth));
+    }
+
+    let tiles_x = (size.x + TILE_SIZE - 1) / TILE_SIZE;
+    let tiles_y = (size.y + TILE_SIZE - 1) / TILE_SIZE;
+    let mut tiles: Vec<Tile> = (0..tiles_x * tiles_y).map(|i| {
+        let min = Point2i { x: (i % tiles_x) * TILE_SIZE, y: (i / tiles_x) * TILE_SIZE };
+        let max = Point2i { x: (min.x + TILE_SIZE).min(size.x), y: (min.y + TILE_SIZE).min(size.y) };
+        let mut rect = BndBox2i::new_empty();
+        rect.add_point(min);
+        rect.add_point(max);
+        Tile {
+            size,
+            rect,
+            anti_aliasing: ctx.anti_aliasing,
+            blend,
+            depth_write: shader.depth_write(),
+            triangles: vec![],
+            depth: vec![],
+            colors: vec![],
+        }
+    }).collect();
+
+    for (i, tri) in triangles.iter().enumerate() {
+        for ty in tri.tiles.min.y..=tri.tiles.max.y {
+            for tx in tri.tiles.min.x..=tri.tiles.max.x {
+                tiles[(tx + ty * tiles_x) as usize].triangles.push(i);
+            }
+        }
+    }
+
+    let samples = ctx.anti_aliasing.samples();
+    let area = (TILE_SIZE * TILE_SIZE) as usize * samples;
+    if blend.is_blending() {
+        // the framebuffer is read here, it isn't required to be shared
+        // between the workers
+        for tile in tiles.iter_mut().filter(|tile| !tile.triangles.is_empty()) {
+            tile.colors = vec![None; area];
+            for y in tile.rect.min.y..tile.rect.max.y {
+                for x in tile.rect.min.x..tile.rect.max.x {
+                    let offset = tile.offset(x, y, 0);
+                    let pixel = image.get_pixel(x, y);
+                    let stored = depth_buf.sample_colors(x, y).unwrap();
+                    for (sample, color) in tile.colors[offset..offset + samples].iter_mut().enumerate() {
+                        *color = stored.get(sample).copied().flatten().or(pixel);
+                    }
+                }
+            }
+        }
+    }
+    let depth_src: &DepthBuffer = depth_buf;
+    tiles.par_iter_mut().filter(|tile| !tile.triangles.is_empty()).for_each(|tile| {
+        tile.depth = vec![DepthBuffer::EMPTY; area];
+        if tile.colors.is_empty() {
+            tile.colors = vec![None; area];
+        }
+        for y in tile.rect.min.y..tile.rect.max.y {
+            for x in tile.rect.min.x..tile.rect.max.x {
+                for sample in 0..samples {
+                    let offset = tile.offset(x, y, sample);
+                    tile.depth[offset] = depth_src.get_sample(x, y, sample).unwrap();
+                }
+            }
+        }
+
+        let triangles_in_tile = std::mem::take(&mut tile.triangles);
+        for &i in &triangles_in_tile {
+            let tri = &triangles[i];
+            rasterize_polygon(&tri.polygon, &tri.varyings, shader, tile);
+        }
+    });
+
+    for tile in tiles.iter().filter(|tile| !tile.colors.is_empty()) {
+        for y in tile.rect.min.y..tile.rect.max.y {
+            for x in tile.rect.min.x..tile.rect.max.x {
+                let offset = tile.offset(x, y, 0);
+                let colors = &tile.colors[offset..offset + samples];
+                if colors.iter().all(Option::is_none) {
+                    continue;
+                }
+                for (sample, color) in colors.iter().enumerate() {
+                    if color.is_some() {
+                        depth_buf.set_sample(x, y, sample, tile.depth[offset + sample]);
+                    }
+                }
+                let color = match colors {
+                    [color] => color.unwrap(),
+                    _ => {
+                        // samples the draw didn't cover keep their color of
+                        // the previous draws, or of the image before them
+                        let background = image.get_pixel(x, y);
+                        let stored = depth_buf.sample_colors_mut(x, y).unwrap();
+                        for (stored, &color) in stored.iter_mut().zip(colors) {
+                            *stored = color.or(*stored).or(background);
+                        }
+                        resolve(stored, background)
+                    }
+                };
+                image.set_pixel(x, y, color);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::draw_mesh_tiled;
+    use crate::math::{Point3f, Vec3f};
+    use crate::math::hvec::HVec4f;
+    use crate::mesh::{IndexedTriangleMesh, Triangle};
+    use crate::obj::load_obj_file;
+    use crate::renderer::{AntiAliasing, BlendMode, Camera, Context, DepthBuffer, FlatShader, Fragment, SampleCount, Shader};
+    use crate::renderer::triangle::draw_3d_triangle_with_shader;
+    use crate::tgaimage::{tga_format, TGAColor, TGAImage};
+
+    #[test]
+    fn same_as_drawing_triangles_one_by_one() {
+        let mesh = load_obj_file("assets/african_head.obj").unwrap();
+        let camera = Camera::perspective(Point3f { x: 1.5, y: 0.5, z: 2.0 },
+                                         Vec3f { x: -1.5, y: -0.5, z: -2.0 },
+                                         Vec3f { x: 0.0, y: 1.0, z: 0.0 },
+                                         40.0f32.to_radians(), 4.0 / 3.0, 0.1, 10.0);
+        let ctx = Context::new(camera, Vec3f { x: 0.0, y: 0.0, z: -1.0 });
+        let shader = FlatShader { ctx: &ctx, color: TGAColor::from_rgb(255, 200, 100) };
+
+        // size not divisible by the tile size
+        let (w, h) = (100, 75);
+        let mut tiled = TGAImage::with_size(w, h, tga_format::RGB);
+        let mut tiled_depth = DepthBuffer::with_size(w, h);
+        draw_mesh_tiled(&mesh, &ctx, &shader, &mut tiled, &mut tiled_depth);
+
+        let mut serial = TGAImage::with_size(w, h, tga_format::RGB);
+        let mut serial_depth = DepthBuffer::with_size(w, h);
+        for face in 0..mesh.triangles.len() {
+            let (p1, v1) = shader.vertex(&mesh, face, 0);
+            let (p2, v2) = shader.vertex(&mesh, face, 1);
+            let (p3, v3) = shader.vertex(&mesh, face, 2);
+            draw_3d_triangle_with_shader([p1, p2, p3], [v1, v2, v3], &ctx, &shader, &mut serial, &mut serial_depth);
+        }
+
+        assert!(tiled == serial);
+        let mut drawn = 0;
+        for y in 0..h {
+            for x in 0..w {
+                assert_eq!(tiled_depth.get(x, y), serial_depth.get(x, y), "@ ({}, {})", x, y);
+                drawn += (tiled_depth.get(x, y) != Some(DepthBuffer::EMPTY)) as i32;
+            }
+        }
+        assert!(drawn > 1000, "{}", drawn);
+    }
+
+    /// White triangles with vertices given in clip space.
+    struct SolidShader;
+
+    impl Shader for SolidShader {
+        type Varyings = ();
+
+        fn vertex(&self, mesh: &IndexedTriangleMesh, face: usize, nth: usize) -> (HVec4f, ()) {
+            (HVec4f::from(mesh.vertex(face, nth)), ())
+        }
+
+        fn fragment(&self, _frag: &Fragment<()>) -> Option<TGAColor> {
+            Some(TGAColor::from_rgb(255, 255, 255))
+        }
+    }
+
+    /// Mesh of the triangles of consecutive vertices in clip space.
+    fn clip_space_mesh(vertices: Vec<Point3f>) -> IndexedTriangleMesh {
+        let triangles = (0..vertices.len() as u32 / 3).map(|i| Triangle {
+            vertices: [3 * i + 1, 3 * i + 2, 3 * i + 3],
+            texcoords: None,
+            normals: None,
+        }).collect();
+        IndexedTriangleMesh { vertices, triangles, texcoords: None, normals: None, tangents: None }
+    }
+
+    /// Draw the meshes one after the other into a 16x16 image sharing a
+    /// depth buffer.
+    fn draw_meshes(meshes: &[IndexedTriangleMesh], anti_aliasing: AntiAliasing) -> TGAImage {
+        let camera = Camera::orthographic(Point3f { x: 0.0, y: 0.0, z: 0.0 },
+                                          Vec3f { x: 0.0, y: 0.0, z: 1.0 },
+                                          Vec3f { x: 0.0, y: 1.0, z: 0.0 },
+                                          1.0, -1.0, 1.0);
+        let mut ctx = Context::new(camera, Vec3f { x: 0.0, y: 0.0, z: 1.0 });
+        ctx.anti_aliasing = anti_aliasing;
+        let mut image = TGAImage::with_size(16, 16, tga_format::RGB);
+        let mut depth = DepthBuffer::with_samples(16, 16, anti_aliasing.samples());
+        for mesh in meshes {
+            draw_mesh_tiled(mesh, &ctx, &SolidShader, &mut image, &mut depth);
+        }
+        image
+    }
+
+    /// Triangle covering the bottom right half of the 16x16 image, with
+    /// the hypotenuse at a shallow angle.
+    fn half_triangle() -> IndexedTriangleMesh {
+        clip_space_mesh(vec![Point3f { x: -1.0, y: -1.0, z: 0.0 },
+                             Point3f { x: 1.0, y: -1.0, z: 0.0 },
+                             Point3f { x: 1.0, y: 0.3, z: 0.0 }])
+    }
+
+    fn draw_half_triangle(anti_aliasing: AntiAliasing) -> TGAImage {
+        draw_meshes(&[half_triangle()], anti_aliasing)
+    }
+
+    #[test]
+    fn msaa_averages_sample_coverage() {
+        let image = draw_half_triangle(AntiAliasing::Msaa(SampleCount::X4));
+        image.write_to_file("assets/test_msaa.tga").unwrap();
+
+        let (mut partial, mut coverage) = (0, 0.0);
+        for y in 0..16 {
+            for x in 0..16 {
+                let value = image.get(x, y).unwrap().r();
+                // averages of 4 black or white samples
+                assert!([0, 64, 128, 191, 255].contains(&value), "{} @ ({}, {})", value, x, y);
+                partial += (value != 0 && value != 255) as i32;
+                coverage += value as f32 / 255.0;
+            }
+        }
+        assert!(partial >= 8, "{}", partial);
+        // the triangle is 16 x 10.4 pixels
+        assert!((coverage - 83.2).abs() < 2.0, "{}", coverage);
+        assert_eq!(image.get(15, 0).unwrap().r(), 255);
+        assert_eq!(image.get(0, 15).unwrap().r(), 0);
+    }
+
+    #[test]
+    fn msaa_resolves_edges_shared_between_draws() {
+        // the rest of the image, the hypotenuse is shared with the triangle
+        let rest = clip_space_mesh(vec![Point3f { x: -1.0, y: -1.0, z: 0.0 },
+                                        Point3f { x: 1.0, y: 0.3, z: 0.0 },
+                                        Point3f { x: 1.0, y: 1.0, z: 0.0 },
+                                        Point3f { x: -1.0, y: -1.0, z: 0.0 },
+                                        Point3f { x: 1.0, y: 1.0, z: 0.0 },
+                                        Point3f { x: -1.0, y: 1.0, z: 0.0 }]);
+        let meshes = [half_triangle(), rest];
+        for anti_aliasing in [AntiAliasing::Msaa(SampleCount::X4), AntiAliasing::Ssaa(SampleCount::X8)] {
+            let image = draw_meshes(&meshes, anti_aliasing);
+            for y in 0..16 {
+                for x in 0..16 {
+                    assert_eq!(image.get(x, y).unwrap().r(), 255, "{:?} @ ({}, {})", anti_aliasing, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ssaa_shades_the_same_samples_as_msaa() {
+        for count in [SampleCount::X2, SampleCount::X4, SampleCount::X8] {
+            let msaa = draw_half_triangle(AntiAliasing::Msaa(count));
+            let ssaa = draw_half_triangle(AntiAliasing::Ssaa(count));
+            assert!(msaa == ssaa, "{:?}", count);
+            assert!(msaa != draw_half_triangle(AntiAliasing::None), "{:?}", count);
+        }
+    }
+
+    /// Triangles with vertices given in clip space, colored by the index
+    /// of the face.
+    struct FaceColorShader {
+        colors: Vec<TGAColor>,
+        blend: BlendMode,
+        depth_write: bool,
+    }
+
+    impl Shader for FaceColorShader {
+        type Varyings = f32;
+
+        fn vertex(&self, mesh: &IndexedTriangleMesh, face: usize, nth: usize) -> (HVec4f, f32) {
+            (HVec4f::from(mesh.vertex(face, nth)), face as f32)
+        }
+
+        fn fragment(&self, frag: &Fragment<f32>) -> Option<TGAColor> {
+            Some(self.colors[frag.varyings.round() as usize])
+        }
+
+        fn blend_mode(&self) -> BlendMode {
+            self.blend
+        }
+
+        fn depth_write(&self) -> bool {
+            self.depth_write
+        }
+    }
+
+    /// Triangles covering the whole screen at the given depths.
+    fn screen_triangles(depths: &[f32]) -> IndexedTriangleMesh {
+        let mut mesh = IndexedTriangleMesh { vertices: vec![], triangles: vec![], texcoords: None, normals: None, tangents: None };
+        for &z in depths {
+            let first = mesh.vertices.len() as u32 + 1;
+            mesh.vertices.extend([Point3f { x: -1.0, y: -1.0, z },
+                                  Point3f { x: 3.0, y: -1.0, z },
+                                  Point3f { x: -1.0, y: 3.0, z }]);
+            mesh.triangles.push(Triangle { vertices: [first, first + 1, first + 2], texcoords: None, normals: None });
+        }
+        mesh
+    }
+
+    #[test]
+    fn blended_triangles_are_drawn_back_to_front() {
+        let camera = Camera::orthographic(Point3f { x: 0.0, y: 0.0, z: 0.0 },
+                                          Vec3f { x: 0.0, y: 0.0, z: 1.0 },
+                                          Vec3f { x: 0.0, y: 1.0, z: 0.0 },
+                                          1.0, -1.0, 1.0);
+        let red = TGAColor::from_rgb(255, 0, 0);
+        let blue = TGAColor::from_rgba(0, 0, 255, 128);
+        let green = TGAColor::from_rgba(0, 255, 0, 128);
+        for anti_aliasing in [AntiAliasing::None, AntiAliasing::Msaa(SampleCount::X4)] {
+            let mut ctx = Context::new(camera.clone(), Vec3f { x: 0.0, y: 0.0, z: 1.0 });
+            ctx.anti_aliasing = anti_aliasing;
+            let mut image = TGAImage::with_size(40, 40, tga_format::RGB);
+            let mut depth = DepthBuffer::with_samples(40, 40, anti_aliasing.samples());
+
+            let opaque = FaceColorShader { colors: vec![red], blend: BlendMode::Replace, depth_write: true };
+            draw_mesh_tiled(&screen_triangles(&[0.5]), &ctx, &opaque, &mut image, &mut depth);
+            let opaque_depth = depth.clone();
+            // the front triangle comes first
+            let transparent = FaceColorShader { colors: vec![blue, green], blend: BlendMode::AlphaOver, depth_write: false };
+            draw_mesh_tiled(&screen_triangles(&[-0.5, 0.0]), &ctx, &transparent, &mut image, &mut depth);
+
+            let expected = BlendMode::AlphaOver.blend(blue, BlendMode::AlphaOver.blend(green, red));
+            for (x, y) in [(0, 0), (20, 20), (39, 39)] {
+                assert_eq!(image.get(x, y).unwrap(), expected, "@ ({}, {}) {:?}", x, y, anti_aliasing);
+                assert_eq!(depth.get(x, y), opaque_depth.get(x, y));
+            }
+        }
+    }
+}
@@ -1,15 +1,21 @@
-use crate::math::{BndBox2i, BndBox2f, Point2f, Point2i, Point3f, Vec2f, Vec3f, Vec3i};
-use crate::renderer::Context;
-use crate::tgaimage::{TGAColor, TGAImage};
+use rayon::prelude::*;
+
+use crate::math::{BndBox2i, Point2, Point2f, Point2i, Point3f, Vec2f, Vec3f};
+use crate::math::hvec::HVec4f;
+use crate::renderer::{AntiAliasing, BlendMode, Context, CullMode, DepthBuffer, FrontFace};
+use crate::renderer::shader::{Fragment, Shader, Varyings};
+use crate::renderer::clip::{clip_triangle, ClipVertex};
+use crate::framebuffer::Framebuffer;
+use crate::tgaimage::TGAColor;
 
 #[allow(dead_code)]
-pub fn draw_triangle_sweep(v1: Point2i, v2: Point2i, v3: Point2i, image: &mut TGAImage, color: TGAColor) {
+pub fn draw_triangle_sweep<F: Framebuffer>(v1: Point2i, v2: Point2i, v3: Point2i, image: &mut F, color: TGAColor) {
     // handle degenerate triangle first
     if v1.y == v2.y && v2.y == v3.y {
         let xl = v1.x.min(v2.x.min(v3.x));
         let xr = v1.x.max(v2.x.max(v3.x));
         for x in xl..=xr {
-            image.set(x, v1.y, color).unwrap();
+            image.set_pixel(x, v1.y, color);
         }
         return;
     }
@@ -33,183 +39,422 @@ pub fn draw_triangle_sweep(v1: Point2i, v2: Point2i, v3: Point2i, image: &mut TG
 
         if xl > xr { std::mem::swap(&mut xl, &mut xr); }
         for x in xl..=xr {
-            image.set(x, p1.y + i, color).unwrap();
+            image.set_pixel(x, p1.y + i, color);
         }
     }
 }
 
-pub fn draw_triangle_parallel(v1: Point2i, v2: Point2i, v3: Point2i, image: &mut TGAImage, color: TGAColor) {
+pub fn draw_triangle_parallel<F: Framebuffer>(v1: Point2i, v2: Point2i, v3: Point2i, image: &mut F, color: TGAColor) {
     if v1.y == v2.y && v2.y == v3.y {
         let xl = v1.x.min(v2.x.min(v3.x));
         let xr = v1.x.max(v2.x.max(v3.x));
-        for x in xl..=xr { image.set(x, v1.y, color).unwrap(); }
+        for x in xl..=xr { image.set_pixel(x, v1.y, color); }
         return;
     } else if v1.x == v2.x && v2.x == v3.x {
         let yt = v1.y.min(v2.y.min(v3.y));
         let yb = v1.y.max(v2.y.max(v3.y));
-        for y in yt..=yb { image.set(v1.x, y, color).unwrap(); }
+        for y in yt..=yb { image.set_pixel(v1.x, y, color); }
         return;
     }
 
 
-    let mut bbox = BndBox2i::new_empty();
-    bbox.add_point(v1);
-    bbox.add_point(v2);
-    bbox.add_point(v3);
+    // integer vertices are at pixel centers
+    let center = |v: Point2i| EdgeFunctions::sample_point(v.x, v.y);
+    let Some(edges) = EdgeFunctions::new([center(v1), center(v2), center(v3)]) else {
+        return
+    };
+    let mut clamp = BndBox2i::new_empty();
+    clamp.add_point(Point2i { x: 0, y: 0 });
+    clamp.add_point(Point2i { x: image.width(), y: image.height() });
+    let (min, max) = edges.pixel_range(&clamp);
+
+    // rows are tested in parallel, the framebuffer is written to afterwards
+    let rows: Vec<Vec<i32>> = (min.y..=max.y).into_par_iter().map(|y| {
+        (min.x..=max.x).filter(|&x| edges.covers(EdgeFunctions::sample_point(x, y))).collect()
+    }).collect();
+    for (y, row) in (min.y..).zip(rows) {
+        for x in row {
+            image.set_pixel(x, y, color);
+        }
+    }
+}
+
+pub fn draw_triangle<F: Framebuffer>(v1: Point2i, v2: Point2i, v3: Point2i, image: &mut F, color: TGAColor) {
+    draw_triangle_parallel(v1, v2, v3, image, color);
+}
+
+/// Number of fractional bits of the fixed point pixel coordinates.
+const SUBPIXEL_BITS: i64 = 8;
+const SUBPIXEL_HALF: i64 = 1 << (SUBPIXEL_BITS - 1);
+/// Limit of the fixed point coordinates, keeps the edge functions within
+/// `i64`. Triangles are clipped to [`guard_band`] to stay within it.
+const SUBPIXEL_LIMIT: i64 = 1 << 29;
+
+/// Guard band in screen sizes the triangles drawn to a `size` target are
+/// clipped against: the one of the context, limited to the widest one whose
+/// pixel coordinates fit the fixed point coordinates.
+pub(crate) fn guard_band(ctx: &Context, size: Point2i) -> f32 {
+    let limit = (SUBPIXEL_LIMIT >> SUBPIXEL_BITS) as f32;
+    let widest = 2.0 * limit / size.x.max(size.y).max(1) as f32 - 1.0;
+    ctx.guard_band.map_or(widest, |g| g.min(widest))
+}
+
+/// Fixed point pixel coordinates of a point in the pixel space.
+fn to_fixed(pnt: Point2f) -> Point2<i64> {
+    let scale = (1 << SUBPIXEL_BITS) as f32;
+    Point2 {
+        x: (pnt.x * scale).round() as i64,
+        y: (pnt.y * scale).round() as i64,
+    }
+}
+
+/// Edge functions of a triangle with fixed point vertex coordinates.
+///
+/// Pixels are sampled at their centers, or at the points of the
+/// anti-aliasing pattern. A sample is covered if it is inside the triangle
+/// or exactly on a top or left edge of it, so samples on the edges shared
+/// by adjacent triangles are drawn exactly once.
+struct EdgeFunctions {
+    /// Vertices in counter-clockwise order.
+    vs: [Point2<i64>; 3],
+    /// Twice the area of the triangle.
+    area: i64,
+    /// Whether the order of the last two vertices was swapped.
+    flipped: bool,
+    /// Whether the edge opposite to each vertex is a top or left one.
+    top_left: [bool; 3],
+}
 
-    let is_inside = |p: Point2i| {
-        // solve linear eqn: p = 1 * v1 + u * v2 + v * v3;
-        let aux1 = Vec3i { x: v2.x - v1.x, y: v3.x - v1.x, z: v1.x - p.x };
-        let aux2 = Vec3i { x: v2.y - v1.y, y: v3.y - v1.y, z: v1.y - p.y };
-        let solution = aux1.cross(aux2);
-        if solution.z == 0 {
-            return false
+impl EdgeFunctions {
+    /// `None` for degenerate triangles.
+    fn new(vs: [Point2<i64>; 3]) -> Option<Self> {
+        let area = Self::edge(vs[0], vs[1], vs[2]);
+        if area == 0 {
+            return None
         }
+        let flipped = area < 0;
+        let vs = if flipped { [vs[0], vs[2], vs[1]] } else { vs };
+
+        // with Y going up a counter-clockwise triangle has its left edges
+        // going down and the top edge going left
+        let is_top_left = |a: Point2<i64>, b: Point2<i64>| b.y < a.y || (b.y == a.y && b.x < a.x);
+        Some(EdgeFunctions {
+            vs,
+            area: area.abs(),
+            flipped,
+            top_left: [is_top_left(vs[1], vs[2]), is_top_left(vs[2], vs[0]), is_top_left(vs[0], vs[1])],
+        })
+    }
 
-        let barycentric = Vec3f {
-            x: 1.0f32 - (solution.x + solution.y) as f32 / solution.z as f32,
-            y: solution.x as f32 / solution.z as f32,
-            z: solution.y as f32 / solution.z as f32,
-        };
-        if barycentric.x < 0.0 || barycentric.y < 0.0 || barycentric.z < 0.0 {
-            return false
-        } else {
-            return true
+    /// Twice the signed area of the triangle (a, b, p), positive if `p` is
+    /// on the left of the a -> b edge.
+    fn edge(a: Point2<i64>, b: Point2<i64>, p: Point2<i64>) -> i64 {
+        (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+    }
+
+    /// Fixed point coordinates of the center of the pixel.
+    fn sample_point(x: i32, y: i32) -> Point2<i64> {
+        Point2 {
+            x: ((x as i64) << SUBPIXEL_BITS) + SUBPIXEL_HALF,
+            y: ((y as i64) << SUBPIXEL_BITS) + SUBPIXEL_HALF,
         }
-    };
+    }
 
-    // TODO: try to parallelize with rayon
-    for x in bbox.min.x..=bbox.max.x {
-        for y in bbox.min.y..=bbox.max.y {
-            if is_inside(Point2i { x, y }) {
-                image.set(x, y, color).unwrap();
-            }
+    /// Fixed point coordinates of a sample given in 1/16 of a pixel
+    /// relative to the pixel `center`.
+    fn offset_point(center: Point2<i64>, (dx, dy): (i32, i32)) -> Point2<i64> {
+        let scale = 1 << (SUBPIXEL_BITS - 4);
+        Point2 { x: center.x + dx as i64 * scale, y: center.y + dy as i64 * scale }
+    }
+
+    fn weights(&self, p: Point2<i64>) -> [i64; 3] {
+        let [v0, v1, v2] = self.vs;
+        [Self::edge(v1, v2, p), Self::edge(v2, v0, p), Self::edge(v0, v1, p)]
+    }
+
+    /// Inclusive range of pixels that may be covered, clamped to `rect`
+    /// with `min` inclusive and `max` exclusive. The range is empty (min is
+    /// greater than max) if the triangle is outside of `rect`.
+    fn pixel_range(&self, rect: &BndBox2i) -> (Point2i, Point2i) {
+        let one = 1 << SUBPIXEL_BITS;
+        let min_x = self.vs.iter().map(|v| v.x).min().unwrap();
+        let max_x = self.vs.iter().map(|v| v.x).max().unwrap();
+        let min_y = self.vs.iter().map(|v| v.y).min().unwrap();
+        let max_y = self.vs.iter().map(|v| v.y).max().unwrap();
+        // pixels overlapping the vertex coordinates range, samples can be
+        // anywhere within the pixel
+        let pixel = |v: i64| v.div_euclid(one);
+
+        let min = Point2i {
+            x: pixel(min_x).max(rect.min.x as i64) as i32,
+            y: pixel(min_y).max(rect.min.y as i64) as i32,
+        };
+        let max = Point2i {
+            x: pixel(max_x).min(rect.max.x as i64 - 1) as i32,
+            y: pixel(max_y).min(rect.max.y as i64 - 1) as i32,
+        };
+        (min, max)
+    }
+
+    /// Whether the sample point is covered according to the fill rule.
+    fn covers(&self, p: Point2<i64>) -> bool {
+        let w = self.weights(p);
+        (0..3).all(|i| w[i] > 0 || (w[i] == 0 && self.top_left[i]))
+    }
+
+    /// Barycentric coordinates of the point in the original vertex order,
+    /// also outside of the triangle.
+    fn barycentric(&self, p: Point2<i64>) -> Point3f {
+        let w = self.weights(p).map(|w| w as f64 / self.area as f64);
+        if self.flipped {
+            Point3f { x: w[0] as f32, y: w[2] as f32, z: w[1] as f32 }
+        } else {
+            Point3f { x: w[0] as f32, y: w[1] as f32, z: w[2] as f32 }
         }
     }
 }
 
-pub fn draw_triangle(v1: Point2i, v2: Point2i, v3: Point2i, image: &mut TGAImage, color: TGAColor) {
-    draw_triangle_parallel(v1, v2, v3, image, color);
+/// Barycentric coordinates in the clip space for the screen space ones,
+/// `inv_w` are reciprocals of W coordinates of the triangle vertices.
+///
+/// Attributes are linear in the clip space, while projection onto the
+/// screen divides by W, so the screen space weights are scaled by 1 / W
+/// and normalized back to unit sum.
+fn perspective_correct(bary: Point3f, inv_w: Vec3f) -> Point3f {
+    let x = bary.x * inv_w.x;
+    let y = bary.y * inv_w.y;
+    let z = bary.z * inv_w.z;
+    let sum = x + y + z;
+    Point3f { x: x / sum, y: y / sum, z: z / sum }
 }
 
-fn barycentric(v1: &Point2f, v2: &Point2f, v3: &Point2f, p: &Point2f) -> Option<Point3f> {
-    // solve linear eqn: p = 1 * v1 + u * v2 + v * v3;
-    let aux1 = Vec3f { x: v2.x - v1.x, y: v3.x - v1.x, z: v1.x - p.x };
-    let aux2 = Vec3f { x: v2.y - v1.y, y: v3.y - v1.y, z: v1.y - p.y };
-    let solution = aux1.cross(aux2);
-    if solution.z.abs() < 1e-2 {
-        return None
-    }
-    Some(Point3f {
-        x: 1.0 - (solution.x + solution.y) / solution.z,
-        y: solution.x / solution.z,
-        z: solution.y / solution.z,
-    })
+/// Pixel space coordinates of a point in the screen space, the [-1, 1]
+/// screen range is mapped onto [0, size]. Pixel (x, y) covers the unit
+/// square with its bottom left corner at (x, y).
+pub(crate) fn to_pixel(pnt: Point2f, width: i32, height: i32) -> Point2f {
+    let ones = Vec2f { x: 1.0, y: 1.0 };
+    let img_half_dims = Point2f { x: 0.5 * width as f32, y: 0.5 * height as f32 };
+    (pnt + ones) * img_half_dims
 }
 
-fn draw_3d_triangle_impl<C>(v1: Point3f,
-                            v2: Point3f,
-                            v3: Point3f,
-                            ctx: &Context,
-                            image: &mut TGAImage,
-                            get_color: C,
-                            z_buf: &mut [f32]) where C: Fn(f32, f32, f32) -> TGAColor {
-    let iw = (image.width - 1) as f32;
-    let ih = (image.height - 1) as f32;
-
-    let local_v1 = ctx.camera.transform(&v1);
-    let local_v2 = ctx.camera.transform(&v2);
-    let local_v3 = ctx.camera.transform(&v3);
-
-    // back face culling
-    let rev_normal = (v3 - v1).cross(v2 - v1).normalize();
-    let intensity = rev_normal.dot(ctx.light);
-    if intensity <= 0.0 {
-        return
+/// Pixels and depth values a triangle is rasterized into.
+pub(crate) trait RasterTarget {
+    /// Size of the whole render target in pixels.
+    fn size(&self) -> Point2i;
+
+    /// Pixels that can be drawn to, `min` inclusive and `max` exclusive.
+    fn rect(&self) -> BndBox2i;
+
+    /// Samples of the pixels, the target stores a depth and a color for
+    /// each one.
+    fn anti_aliasing(&self) -> AntiAliasing;
+
+    /// Stored depth of a pixel sample within the rectangle.
+    fn depth(&self, x: i32, y: i32, sample: usize) -> f32;
+
+    /// Store the fragment that passed the depth test of the sample, blended
+    /// and with its depth written according to the shader.
+    fn write(&mut self, x: i32, y: i32, sample: usize, depth: f32, color: TGAColor);
+}
+
+/// Whole image with its depth buffer, without anti-aliasing.
+struct ImageTarget<'a, F> {
+    image: &'a mut F,
+    depth_buf: &'a mut DepthBuffer,
+    blend: BlendMode,
+    depth_write: bool,
+}
+
+impl<F: Framebuffer> RasterTarget for ImageTarget<'_, F> {
+    fn size(&self) -> Point2i {
+        Point2i { x: self.image.width(), y: self.image.height() }
     }
 
-    // conversion from screen space into pixel coordinates
-    // for X and Y: [-1, 1] -> [0, img_dim]
-    let ones = Vec2f { x: 1.0, y: 1.0 };
-    let img_half_dims = Point2f { x: 0.5 * iw, y: 0.5 * ih };
-    let half_px_offset = Vec2f { x: 0.5, y: 0.5 };
-    let flat_v1 = ((local_v1.drop_z() + ones) * img_half_dims + half_px_offset).trunc();
-    let flat_v2 = ((local_v2.drop_z() + ones) * img_half_dims + half_px_offset).trunc();
-    let flat_v3 = ((local_v3.drop_z() + ones) * img_half_dims + half_px_offset).trunc();
-
-    let mut clamp = BndBox2f::new_empty();
-    clamp.add_point(Point2f { x: 0.0, y: 0.0 });
-    clamp.add_point(Point2f { x: iw, y: ih });
-    let mut bbox = BndBox2f::new_empty();
-    bbox.add_point(flat_v1);
-    bbox.add_point(flat_v2);
-    bbox.add_point(flat_v3);
-    bbox.clamp_by(&clamp);
-
-    let num_steps_x = (bbox.max.x - bbox.min.x) as i32;
-    let num_steps_y = (bbox.max.y - bbox.min.y) as i32;
-    for x_off in 0..=num_steps_x {
-        for y_off in 0..=num_steps_y {
-            let pnt = Point2f {
-                x: bbox.min.x + x_off as f32,
-                y: bbox.min.y + y_off as f32,
-            };
-            if let Some(bary) = barycentric(&flat_v1, &flat_v2, &flat_v3, &pnt) {
-                let x = pnt.x as i32;
-                let y = pnt.y as i32;
-                let z = local_v1.z * bary.x +
-                        local_v2.z * bary.y +
-                        local_v3.z * bary.z;
-                if bary.x < 0.0 || bary.y < 0.0 || bary.z < 0.0 {
+    fn rect(&self) -> BndBox2i {
+        let mut rect = BndBox2i::new_empty();
+        rect.add_point(Point2i { x: 0, y: 0 });
+        rect.add_point(Point2i {
+            x: self.image.width().min(self.depth_buf.width),
+            y: self.image.height().min(self.depth_buf.height),
+        });
+        rect
+    }
+
+    fn anti_aliasing(&self) -> AntiAliasing {
+        AntiAliasing::None
+    }
+
+    fn depth(&self, x: i32, y: i32, _sample: usize) -> f32 {
+        self.depth_buf.get(x, y).unwrap()
+    }
+
+    fn write(&mut self, x: i32, y: i32, _sample: usize, depth: f32, color: TGAColor) {
+        if self.depth_write {
+            self.depth_buf.set(x, y, depth);
+        }
+        let color = match self.image.get_pixel(x, y) {
+            Some(dst) if self.blend.is_blending() => self.blend.blend(color, dst),
+            _ => color,
+        };
+        self.image.set_pixel(x, y, color);
+    }
+}
+
+fn rasterize_clipped<T, C>(cv1: &ClipVertex,
+                          cv2: &ClipVertex,
+                          cv3: &ClipVertex,
+                          target: &mut T,
+                          fragment: &C) where T: RasterTarget, C: Fn(i32, i32, f32, [Vec3f; 3]) -> Option<TGAColor> {
+    let rect = target.rect();
+    if rect.max.x <= rect.min.x || rect.max.y <= rect.min.y {
+        return;
+    }
+
+    let inv_w = Vec3f { x: cv1.pos.w.recip(), y: cv2.pos.w.recip(), z: cv3.pos.w.recip() };
+    let local_v1 = cv1.pos.to_point3();
+    let local_v2 = cv2.pos.to_point3();
+    let local_v3 = cv3.pos.to_point3();
+
+    let size = target.size();
+    let flat_v1 = to_fixed(to_pixel(local_v1.drop_z(), size.x, size.y));
+    let flat_v2 = to_fixed(to_pixel(local_v2.drop_z(), size.x, size.y));
+    let flat_v3 = to_fixed(to_pixel(local_v3.drop_z(), size.x, size.y));
+    let Some(edges) = EdgeFunctions::new([flat_v1, flat_v2, flat_v3]) else {
+        return
+    };
+
+    // depth is divided by W already, so it is linear in screen space
+    let depth = |bary: Point3f| local_v1.z * bary.x + local_v2.z * bary.y + local_v3.z * bary.z;
+    // perspective correct barycentric coordinates relative to the unclipped
+    // triangle
+    let orig_bary = |b: Point3f| {
+        let b = perspective_correct(b, inv_w);
+        cv1.bary * b.x + cv2.bary * b.y + cv3.bary * b.z
+    };
+    let one = 1 << SUBPIXEL_BITS;
+    let shade = |x: i32, y: i32, p: Point2<i64>| {
+        let bary = edges.barycentric(p);
+        let center = orig_bary(bary);
+        let bary_dx = orig_bary(edges.barycentric(Point2 { x: p.x + one, y: p.y })) - center;
+        let bary_dy = orig_bary(edges.barycentric(Point2 { x: p.x, y: p.y + one })) - center;
+        fragment(x, y, depth(bary), [center, bary_dx, bary_dy])
+    };
+
+    let anti_aliasing = target.anti_aliasing();
+    let offsets = anti_aliasing.sample_offsets();
+    let (min, max) = edges.pixel_range(&rect);
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            let center = EdgeFunctions::sample_point(x, y);
+            // without supersampling the fragment stage runs once for all
+            // the samples, at the pixel center if it is covered and at the
+            // first covered sample otherwise
+            let mut pixel_shade = None;
+            for (sample, &offset) in offsets.iter().enumerate() {
+                let p = EdgeFunctions::offset_point(center, offset);
+                if !edges.covers(p) {
                     continue;
                 }
-
-                if z_buf[(x + image.width * y) as usize] > z {
-                    let shade = get_color(bary.x, bary.y, bary.z).scale(intensity);
-                    z_buf[(x + image.width * y) as usize] = z;
-                    image.set(x, y, shade).unwrap();
+                let z = depth(edges.barycentric(p));
+                if target.depth(x, y, sample) <= z {
+                    continue;
+                }
+                let color = if anti_aliasing.shades_samples() {
+                    shade(x, y, p)
+                } else {
+                    *pixel_shade.get_or_insert_with(|| shade(x, y, if edges.covers(center) { center } else { p }))
+                };
+                if let Some(color) = color {
+                    target.write(x, y, sample, z, color);
                 }
             }
         }
     }
 }
 
-pub fn draw_3d_triangle(v1: Point3f,
-                        v2: Point3f,
-                        v3: Point3f,
-                        ctx: &Context,
-                        image: &mut TGAImage,
-                        color: TGAColor,
-                        z_buf: &mut [f32]) {
-    let const_color = |_, _, _| { color };
-    draw_3d_triangle_impl(v1, v2, v3, ctx, image, const_color, z_buf);
+/// Whether the clipped triangle is skipped by the cull mode of the context
+/// for its winding on the screen.
+pub(crate) fn is_culled(polygon: &[ClipVertex], ctx: &Context) -> bool {
+    if ctx.cull_mode == CullMode::None || polygon.len() < 3 {
+        return false
+    }
+    // twice the signed area of the polygon, positive if counter-clockwise
+    let mut area = 0.0;
+    for (i, cur) in polygon.iter().enumerate() {
+        let (a, b) = (cur.pos.to_point3(), polygon[(i + 1) % polygon.len()].pos.to_point3());
+        area += a.x * b.y - b.x * a.y;
+    }
+    if area == 0.0 {
+        return false
+    }
+    let front = match ctx.front_face {
+        FrontFace::Ccw => area > 0.0,
+        FrontFace::Cw => area < 0.0,
+    };
+    match ctx.cull_mode {
+        CullMode::Back => !front,
+        CullMode::Front => front,
+        CullMode::None => false,
+    }
 }
 
-pub fn draw_3d_triangle_textured(v1: Point3f,
-                                 v2: Point3f,
-                                 v3: Point3f,
-                                 tc1: Point2f,
-                                 tc2: Point2f,
-                                 tc3: Point2f,
-                                 ctx: &Context,
-                                 image: &mut TGAImage,
-                                 diff_texture: &TGAImage,
-                                 z_buf: &mut [f32]) {
-    let diff_texture_picker = |l1, l2, l3| {
-        let texpnt: Vec2f = l1 * <Point2f as Into<Vec2f>>::into(tc1) +
-                            l2 * <Point2f as Into<Vec2f>>::into(tc2) +
-                            l3 * <Point2f as Into<Vec2f>>::into(tc3);
-        diff_texture.get((texpnt.x * diff_texture.width  as f32) as i32,
-                         (texpnt.y * diff_texture.height as f32) as i32).unwrap()
+/// Rasterize a clipped triangle, `polygon` is the result of clipping of the
+/// triangle with vertex `varyings`.
+pub(crate) fn rasterize_polygon<T, S>(polygon: &[ClipVertex],
+                                      varyings: &[S::Varyings; 3],
+                                      shader: &S,
+                                      target: &mut T) where T: RasterTarget, S: Shader {
+    // barycentric coordinates of the pixel and their change to the next
+    // pixel along X and Y
+    let fragment = |x, y, depth, [bary, bary_dx, bary_dy]: [Vec3f; 3]| {
+        shader.fragment(&Fragment {
+            x,
+            y,
+            depth,
+            varyings: S::Varyings::interpolate(varyings, bary),
+            ddx: S::Varyings::interpolate(varyings, bary_dx),
+            ddy: S::Varyings::interpolate(varyings, bary_dy),
+        })
     };
+    for i in 1..polygon.len().saturating_sub(1) {
+        rasterize_clipped(&polygon[0], &polygon[i], &polygon[i + 1], target, &fragment);
+    }
+}
 
-    draw_3d_triangle_impl(v1, v2, v3, ctx, image, diff_texture_picker, z_buf);
+/// Draw a triangle given by clip space vertex positions and varyings
+/// produced by the vertex stage of `shader`. The triangle is culled
+/// according to the context and drawn without anti-aliasing.
+pub fn draw_3d_triangle_with_shader<F, S>(clip_pos: [HVec4f; 3],
+                                          varyings: [S::Varyings; 3],
+                                          ctx: &Context,
+                                          shader: &S,
+                                          image: &mut F,
+                                          depth_buf: &mut DepthBuffer) where F: Framebuffer, S: Shader {
+    let tri = ClipVertex::triangle(clip_pos);
+    let size = Point2i { x: image.width(), y: image.height() };
+    let polygon = clip_triangle(&tri, Some(guard_band(ctx, size)));
+    if is_culled(&polygon, ctx) {
+        return;
+    }
+    let mut target = ImageTarget {
+        image,
+        depth_buf,
+        blend: shader.blend_mode(),
+        depth_write: shader.depth_write(),
+    };
+    rasterize_polygon(&polygon, &varyings, shader, &mut target);
 }
 
 #[cfg(test)]
 mod test {
-    use super::{draw_triangle, TGAImage, TGAColor, Point2i};
-    use crate::tgaimage::tga_format;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{draw_3d_triangle_with_shader, draw_triangle, TGAColor, Point2f, Point2i, Point3f, Vec3f};
+    use crate::framebuffer::Framebuffer;
+    use crate::math::hvec::HVec4f;
+    use crate::mesh::{IndexedTriangleMesh, Triangle};
+    use crate::renderer::{draw_mesh_with_shader, Camera, Context, CullMode, DepthBuffer, Fragment, FrontFace, Shader};
+    use crate::tgaimage::{tga_format, TGAImage};
 
     fn setup_1_image() -> (TGAImage, TGAColor) {
         (TGAImage::with_size(6, 6, tga_format::RGB),
@@ -286,11 +531,12 @@ mod test {
             Point2i { x: 5, y: 5 },
             Point2i { x: 0, y: 5 },
         ];
+        // pixels on the bottom and right edges are left out by the fill rule
         let preds = [
-            |x, y| x >= y,
-            |x, y| x + y >= 5,
-            |x, y| y >= x,
-            |x, y| x + y <= 5,
+            |x, y| x >= y && y > 0 && x < 5,
+            |x, y| x + y >= 5 && x < 5,
+            |x, y| y > x,
+            |x, y| x + y < 5 && y > 0,
         ];
 
         for i in 0..4 {
@@ -307,5 +553,259 @@ mod test {
             img.clear();
         }
     }
-}
 
+    struct CheckerShader<'a> {
+        ctx: &'a Context,
+    }
+
+    impl Shader for CheckerShader<'_> {
+        type Varyings = Point2f;
+
+        fn vertex(&self, mesh: &IndexedTriangleMesh, face: usize, nth: usize) -> (HVec4f, Point2f) {
+            let pos = self.ctx.camera.view_projection_matrix() * HVec4f::from(mesh.vertex(face, nth));
+            (pos, mesh.texcoord(face, nth).unwrap())
+        }
+
+        fn fragment(&self, frag: &Fragment<Point2f>) -> Option<TGAColor> {
+            Some(checker_color(frag.varyings))
+        }
+    }
+
+    fn checker_color(uv: Point2f) -> TGAColor {
+        let check = (uv.x * 8.0).floor() as i32 + (uv.y * 8.0).floor() as i32;
+        if check % 2 == 0 { TGAColor::from_rgb(255, 255, 255) } else { TGAColor::from_rgb(100, 100, 100) }
+    }
+
+    #[test]
+    fn perspective_correct_checkerboard() {
+        const SIZE: i32 = 64;
+        let camera = Camera::perspective(Point3f { x: 0.0, y: 0.3, z: 0.0 },
+                                         Vec3f { x: 0.0, y: -0.15, z: -1.0 },
+                                         Vec3f { x: 0.0, y: 1.0, z: 0.0 },
+                                         60.0f32.to_radians(), 1.0, 0.1, 100.0);
+        let ctx = Context::new(camera, Vec3f { x: 0.0, y: -1.0, z: 0.0 });
+
+        // floor quad going away from the camera, seen at a grazing angle
+        let (near_z, far_z) = (-0.5, -8.0);
+        let mesh = IndexedTriangleMesh {
+            vertices: vec![Point3f { x: -1.0, y: 0.0, z: near_z },
+                           Point3f { x: 1.0, y: 0.0, z: near_z },
+                           Point3f { x: 1.0, y: 0.0, z: far_z },
+                           Point3f { x: -1.0, y: 0.0, z: far_z }],
+            triangles: vec![Triangle { vertices: [1, 2, 3], texcoords: Some([1, 2, 3]), normals: None },
+                            Triangle { vertices: [1, 3, 4], texcoords: Some([1, 3, 4]), normals: None }],
+            texcoords: Some(vec![Point2f { x: 0.0, y: 0.0 },
+                                 Point2f { x: 1.0, y: 0.0 },
+                                 Point2f { x: 1.0, y: 1.0 },
+                                 Point2f { x: 0.0, y: 1.0 }]),
+            normals: None,
+            tangents: None,
+        };
+        let mut img = TGAImage::with_size(SIZE, SIZE, tga_format::RGB);
+        draw_mesh_with_shader(&mesh, &ctx, &CheckerShader { ctx: &ctx }, &mut img);
+        img.write_to_file("assets/test_perspective_checker.tga").unwrap();
+
+        // texture coordinates of the floor point seen through the pixel
+        let inv_view_proj = ctx.camera.view_projection_matrix().inverse().unwrap();
+        let ray_cast = |x: i32, y: i32| -> Option<Point2f> {
+            let ndc_x = 2.0 * (x as f32 + 0.5) / SIZE as f32 - 1.0;
+            let ndc_y = 2.0 * (y as f32 + 0.5) / SIZE as f32 - 1.0;
+            let a = Vec3f::from(inv_view_proj.transform_point(Point3f { x: ndc_x, y: ndc_y, z: -1.0 }));
+            let b = Vec3f::from(inv_view_proj.transform_point(Point3f { x: ndc_x, y: ndc_y, z: 1.0 }));
+            let t = a.y / (a.y - b.y);
+            let hit = a + (b - a) * t;
+            let uv = Point2f { x: (hit.x + 1.0) / 2.0, y: (near_z - hit.z) / (near_z - far_z) };
+            let inside = t > 0.0 && (0.0..1.0).contains(&uv.x) && (0.0..1.0).contains(&uv.y);
+            inside.then_some(uv)
+        };
+
+        // pixels next to the check edges may go either way
+        let mut checked = 0;
+        for y in 1..SIZE - 1 {
+            for x in 1..SIZE - 1 {
+                let colors: Option<Vec<_>> = (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+                    .map(|(dx, dy)| ray_cast(x + dx, y + dy).map(checker_color))
+                    .collect();
+                let Some(colors) = colors else { continue };
+                if colors.iter().any(|&c| c != colors[0]) {
+                    continue;
+                }
+                assert_eq!(img.get(x, y).unwrap(), colors[0], "@ ({}, {})", x, y);
+                checked += 1;
+            }
+        }
+        assert!(checked > 500, "{}", checked);
+    }
+
+    /// Framebuffer counting how many times each pixel was drawn.
+    struct CountingFramebuffer {
+        width: i32,
+        height: i32,
+        counts: Vec<u32>,
+    }
+
+    impl CountingFramebuffer {
+        fn with_size(width: i32, height: i32) -> Self {
+            CountingFramebuffer { width, height, counts: vec![0; (width * height) as usize] }
+        }
+
+        fn assert_covered_once(&self) {
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    assert_eq!(self.counts[(x + y * self.width) as usize], 1, "@ ({}, {})", x, y);
+                }
+            }
+        }
+    }
+
+    impl Framebuffer for CountingFramebuffer {
+        fn width(&self) -> i32 {
+            self.width
+        }
+
+        fn height(&self) -> i32 {
+            self.height
+        }
+
+        fn get_pixel(&self, _x: i32, _y: i32) -> Option<TGAColor> {
+            None
+        }
+
+        fn set_pixel(&mut self, x: i32, y: i32, _color: TGAColor) -> bool {
+            if x < 0 || y < 0 || x >= self.width || y >= self.height {
+                return false
+            }
+            self.counts[(x + y * self.width) as usize] += 1;
+            true
+        }
+    }
+
+    /// Triangulated grid of jittered points covering a larger area than
+    /// [0, 40] x [0, 30], jitter is a multiple of `step`.
+    fn jittered_grid(step: f32) -> Vec<[Point2f; 3]> {
+        const N: usize = 7;
+        let mut rng = StdRng::seed_from_u64(13);
+        let mut points = [[Point2f { x: 0.0, y: 0.0 }; N + 1]; N + 1];
+        for (i, row) in points.iter_mut().enumerate() {
+            for (j, pnt) in row.iter_mut().enumerate() {
+                let jitter = |rng: &mut StdRng| rng.gen_range(-12..=12) as f32 * step;
+                pnt.x = -10.0 + j as f32 * 9.0 + jitter(&mut rng);
+                pnt.y = -10.0 + i as f32 * 7.0 + jitter(&mut rng);
+            }
+        }
+
+        let mut triangles = vec![];
+        for i in 0..N {
+            for j in 0..N {
+                let (a, b) = (points[i][j], points[i][j + 1]);
+                let (c, d) = (points[i + 1][j + 1], points[i + 1][j]);
+                // alternate the diagonals and the windings
+                if (i + j) % 2 == 0 {
+                    triangles.extend([[a, b, c], [a, d, c]]);
+                } else {
+                    triangles.extend([[a, b, d], [b, c, d]]);
+                }
+            }
+        }
+        triangles
+    }
+
+    #[test]
+    fn adjacent_triangles_cover_pixels_once() {
+        let mut img = CountingFramebuffer::with_size(40, 30);
+        for [a, b, c] in jittered_grid(0.25) {
+            let to_point2i = |p: Point2f| Point2i { x: p.x.round() as i32, y: p.y.round() as i32 };
+            draw_triangle(to_point2i(a), to_point2i(b), to_point2i(c), &mut img, TGAColor::from_rgb(255, 255, 255));
+        }
+        img.assert_covered_once();
+    }
+
+    struct SolidShader;
+
+    impl Shader for SolidShader {
+        type Varyings = ();
+
+        fn vertex(&self, mesh: &IndexedTriangleMesh, face: usize, nth: usize) -> (HVec4f, ()) {
+            (HVec4f::from(mesh.vertex(face, nth)), ())
+        }
+
+        fn fragment(&self, _frag: &Fragment<()>) -> Option<TGAColor> {
+            Some(TGAColor::from_rgb(255, 255, 255))
+        }
+    }
+
+    #[test]
+    fn adjacent_sub_pixel_triangles_cover_pixels_once() {
+        let (w, h) = (40, 30);
+        let camera = Camera::orthographic(Point3f { x: 0.0, y: 0.0, z: 0.0 },
+                                          Vec3f { x: 0.0, y: 0.0, z: 1.0 },
+                                          Vec3f { x: 0.0, y: 1.0, z: 0.0 },
+                                          1.0, -1.0, 1.0);
+        let mut ctx = Context::new(camera, Vec3f { x: 0.0, y: 0.0, z: 1.0 });
+        ctx.cull_mode = CullMode::None;
+        let mut img = CountingFramebuffer::with_size(w, h);
+        // vertices on a 1/16 pixel grid often land exactly on pixel centers
+        for tri in jittered_grid(1.0 / 16.0) {
+            let clip_pos = tri.map(|p| HVec4f { x: p.x / w as f32 * 2.0 - 1.0, y: p.y / h as f32 * 2.0 - 1.0, z: 0.0, w: 1.0 });
+            // a depth buffer per triangle, so that depth test doesn't hide
+            // the pixels drawn twice
+            let mut depth_buf = DepthBuffer::with_size(w, h);
+            draw_3d_triangle_with_shader(clip_pos, [(); 3], &ctx, &SolidShader, &mut img, &mut depth_buf);
+        }
+        img.assert_covered_once();
+    }
+
+    #[test]
+    fn far_vertices_keep_the_edge_slopes() {
+        let camera = Camera::orthographic(Point3f { x: 0.0, y: 0.0, z: 0.0 },
+                                          Vec3f { x: 0.0, y: 0.0, z: 1.0 },
+                                          Vec3f { x: 0.0, y: 1.0, z: 0.0 },
+                                          1.0, -1.0, 1.0);
+        // the far vertex is millions of pixels away, on the line through
+        // the lower left corner with the slope 0.3
+        let far = 1e6;
+        let clip_pos = [(-1.0, -1.0), (far, -1.0), (far, 0.3 * (far + 1.0) - 1.0)]
+            .map(|(x, y)| HVec4f { x, y, z: 0.0, w: 1.0 });
+        for guard_band in [None, Some(Context::DEFAULT_GUARD_BAND), Some(1e9)] {
+            let mut ctx = Context::new(camera.clone(), Vec3f { x: 0.0, y: 0.0, z: 1.0 });
+            ctx.guard_band = guard_band;
+            let mut img = TGAImage::with_size(16, 16, tga_format::RGB);
+            let mut depth_buf = DepthBuffer::with_size(16, 16);
+            draw_3d_triangle_with_shader(clip_pos, [(); 3], &ctx, &SolidShader, &mut img, &mut depth_buf);
+            for y in 0..16 {
+                for x in 0..16 {
+                    let below = (y as f32 + 0.5) < 0.3 * (x as f32 + 0.5);
+                    assert_eq!(img.get(x, y).unwrap().r() == 255, below, "{:?} @ ({}, {})", guard_band, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cull_by_screen_winding() {
+        let camera = Camera::orthographic(Point3f { x: 0.0, y: 0.0, z: 0.0 },
+                                          Vec3f { x: 0.0, y: 0.0, z: 1.0 },
+                                          Vec3f { x: 0.0, y: 1.0, z: 0.0 },
+                                          1.0, -1.0, 1.0);
+        let ccw = [(-0.5, -0.5), (0.5, -0.5), (0.0, 0.5)].map(|(x, y)| HVec4f { x, y, z: 0.0, w: 1.0 });
+        let cw = [ccw[0], ccw[2], ccw[1]];
+        let drawn = |cull_mode, front_face, clip_pos| {
+            let mut ctx = Context::new(camera.clone(), Vec3f { x: 0.0, y: 0.0, z: 1.0 });
+            ctx.cull_mode = cull_mode;
+            ctx.front_face = front_face;
+            let mut img = CountingFramebuffer::with_size(8, 8);
+            let mut depth_buf = DepthBuffer::with_size(8, 8);
+            draw_3d_triangle_with_shader(clip_pos, [(); 3], &ctx, &SolidShader, &mut img, &mut depth_buf);
+            img.counts.iter().sum::<u32>() > 0
+        };
+
+        assert!(drawn(CullMode::Back, FrontFace::Ccw, ccw));
+        assert!(!drawn(CullMode::Back, FrontFace::Ccw, cw));
+        assert!(!drawn(CullMode::Front, FrontFace::Ccw, ccw));
+        assert!(drawn(CullMode::Front, FrontFace::Ccw, cw));
+        assert!(!drawn(CullMode::Back, FrontFace::Cw, ccw));
+        assert!(drawn(CullMode::Back, FrontFace::Cw, cw));
+        assert!(drawn(CullMode::None, FrontFace::Ccw, ccw));
+        assert!(drawn(CullMode::None, FrontFace::Ccw, cw));
+    }
+}
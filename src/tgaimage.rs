@@ -3,6 +3,13 @@ use std::convert::From;
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Read, Write};
 
+mod bmp;
+mod netpbm;
+mod png;
+mod zlib;
+
+pub use netpbm::NetpbmFormat;
+
 pub mod tga_format {
     pub const GRAYSCALE: i32 = 1;
     pub const RGB: i32 = 3;
@@ -47,11 +54,18 @@ struct TGAHeader {
 impl TGAHeader {
     const HEADER_LEN: usize = 18;
 
-    fn from_image(img: &TGAImage) -> TGAHeader {
+    fn from_image(img: &TGAImage, rle: bool) -> TGAHeader {
+        let alpha_bits = if img.bytespp == tga_format::RGBA { 8 } else { 0 };
+        let data_type_code = match (img.bytespp == tga_format::GRAYSCALE, rle) {
+            (false, false) => 2,
+            (true, false) => 3,
+            (false, true) => 10,
+            (true, true) => 11,
+        };
         TGAHeader {
             id_length: 0,
             color_map_type: 0,
-            data_type_code: if img.bytespp == tga_format::GRAYSCALE { 11 } else { 10 },
+            data_type_code,
             color_map_origin: 0,
             color_map_length: 0,
             color_map_depth: 0,
@@ -60,7 +74,7 @@ impl TGAHeader {
             width: img.width as i16,
             height: img.height as i16,
             bits_per_pixel: (img.bytespp * 8) as i8,
-            image_descriptor: 0x20,
+            image_descriptor: (img.origin.descriptor_bits() | alpha_bits) as i8,
         }
     }
 
@@ -159,59 +173,163 @@ impl TGAColor {
     pub fn g(&self) -> u8 { self.val[1] }
     pub fn b(&self) -> u8 { self.val[0] }
     pub fn a(&self) -> u8 { self.val[3] }
+    pub fn bytespp(&self) -> i32 { self.bytespp }
+    /// Raw components in the stored (BGRA) order.
+    pub fn components(&self) -> &[u8] { &self.val[..self.bytespp as usize] }
 
     pub fn scale(&self, factor: f32) -> Self {
-        let mut res_val = [0u8; 4];
-        for i in 0..4 {
-            res_val[i] = (self.val[i] as f32 * factor) as u8;
+        let res_val = self.val.map(|v| (v as f32 * factor) as u8);
+        Self { val: res_val, bytespp: self.bytespp }
+    }
+
+    /// Add `other` scaled by `factor` to the color components, saturating
+    /// at 255. Alpha is kept intact.
+    pub fn add_scaled(&self, other: TGAColor, factor: f32) -> Self {
+        let mut res_val = self.val;
+        for (res, add) in res_val.iter_mut().zip(other.val).take(3) {
+            *res = (*res as f32 + add as f32 * factor).min(255.0) as u8;
+        }
+        Self { val: res_val, bytespp: self.bytespp }
+    }
+
+    /// Scale the red, green and blue components by separate factors,
+    /// saturating at 255. Grayscale colors are scaled by the mean factor
+    /// and alpha is kept intact.
+    pub fn modulate(&self, r: f32, g: f32, b: f32) -> Self {
+        let mut res_val = self.val;
+        if self.bytespp == 1 {
+            res_val[0] = (res_val[0] as f32 * (r + g + b) / 3.0) as u8;
+        } else {
+            for (res, factor) in res_val.iter_mut().zip([b, g, r]) {
+                *res = (*res as f32 * factor) as u8;
+            }
         }
         Self { val: res_val, bytespp: self.bytespp }
     }
+
+    /// Blend of the color with `other`, `t` is the weight of `other` in
+    /// [0, 1]. The result has the number of components of `self`.
+    pub fn lerp(&self, other: TGAColor, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let mut res_val = self.val;
+        for (res, to) in res_val.iter_mut().zip(other.val) {
+            *res = (*res as f32 + (to as f32 - *res as f32) * t).round() as u8;
+        }
+        Self { val: res_val, bytespp: self.bytespp }
+    }
+}
+
+/// Corner of the picture the first pixel in memory is at, the rows and the
+/// pixels within them go away from it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TGAOrigin {
+    /// Rows go up, the layout the renderer draws in.
+    #[default]
+    BottomLeft,
+    BottomRight,
+    /// Rows go down, as in most other image formats.
+    TopLeft,
+    TopRight,
+}
+
+impl TGAOrigin {
+    fn from_descriptor(descriptor: u8) -> TGAOrigin {
+        match (descriptor >> 4) & 0x3 {
+            0 => TGAOrigin::BottomLeft,
+            1 => TGAOrigin::BottomRight,
+            2 => TGAOrigin::TopLeft,
+            _ => TGAOrigin::TopRight,
+        }
+    }
+
+    /// Bits 4 (right) and 5 (top) of the image descriptor.
+    fn descriptor_bits(self) -> u8 {
+        ((self.is_right() as u8) << 4) | ((self.is_top() as u8) << 5)
+    }
+
+    pub fn is_top(self) -> bool {
+        matches!(self, TGAOrigin::TopLeft | TGAOrigin::TopRight)
+    }
+
+    pub fn is_right(self) -> bool {
+        matches!(self, TGAOrigin::BottomRight | TGAOrigin::TopRight)
+    }
 }
 
+/// Image in memory, pixel coordinates are relative to its `origin`.
 #[derive(Clone, Debug, PartialEq)]
 pub struct TGAImage {
     data: Vec<u8>,
     pub width: i32,
     pub height: i32,
-    bytespp: i32
+    bytespp: i32,
+    origin: TGAOrigin,
 }
 
-fn load_rle_data<R: Read>(src: &mut R, img: &mut TGAImage) ->TGAResult<()> {
+/// Decode `img_data_size` bytes of run-length encoded pixels of `bpp` bytes
+/// each.
+fn load_rle_data<R: Read>(src: &mut R, img_data_size: usize, bpp: usize) -> TGAResult<Vec<u8>> {
     const MAX_CHUNK: usize = 128;
     const MAX_BPP: usize = 4;
-    let bpp = img.bytespp as usize;
-    let img_data_size = img.data.len();
+    assert!(bpp <= MAX_BPP);
 
+    // grown as the packets are read, a short stream doesn't allocate the
+    // whole image its header promises
+    let mut data = vec![];
     let mut buf = [0u8; MAX_CHUNK * MAX_BPP];
-    let mut i: usize = 0;
-    while i < img_data_size {
+    while data.len() < img_data_size {
         src.read_exact(&mut buf[..1])?;
         let raw_run = buf[0] & 0x80 == 0;
         let run_length = (buf[0] & 0x7F) as usize + 1;
+        if data.len() + bpp * run_length > img_data_size {
+            return Err(TGAError::IncorrectFileLayout)
+        }
         if raw_run {
             let chunk_size = bpp * run_length;
-            assert!(chunk_size <= MAX_CHUNK * MAX_BPP, "Too long of a raw run detected");
-            if chunk_size > MAX_CHUNK * MAX_BPP {
-                return Err(TGAError::IncorrectFileLayout)
-            }
-
             src.read_exact(&mut buf[..chunk_size])?;
-            img.data[i..(i + chunk_size)].copy_from_slice(&buf[..chunk_size]);
-            i += chunk_size;
+            data.extend_from_slice(&buf[..chunk_size]);
         } else {
             src.read_exact(&mut buf[..bpp])?;
             for _ in 0..run_length {
-                img.data[i..(i + bpp)].copy_from_slice(&buf[..bpp]);
-                i += bpp;
+                data.extend_from_slice(&buf[..bpp]);
             }
         }
     }
 
-    if i == img_data_size {
-        Ok(())
+    Ok(data)
+}
+
+/// Bytes per pixel of the image holding color pixels stored with `depth`
+/// bits in the file, `None` for unsupported depths. 16 bit pixels have
+/// alpha only if the header says that they have an alpha bit.
+fn color_bytespp(depth: u8, alpha_bits: u8) -> Option<i32> {
+    match depth {
+        15 => Some(tga_format::RGB),
+        16 if alpha_bits > 0 => Some(tga_format::RGBA),
+        16 | 24 => Some(tga_format::RGB),
+        32 => Some(tga_format::RGBA),
+        _ => None,
+    }
+}
+
+/// Convert a pixel stored with `depth` bits in the file into the image
+/// components `dst`.
+fn convert_pixel(src: &[u8], depth: u8, dst: &mut [u8]) {
+    if depth == 15 || depth == 16 {
+        // ARRRRRGG GGGBBBBB, 5 bit components are expanded to 8 bits
+        let v = u16::from_le_bytes([src[0], src[1]]);
+        let component = |shift: u16| {
+            let c = ((v >> shift) & 0x1F) as u8;
+            (c << 3) | (c >> 2)
+        };
+        dst[0] = component(0);
+        dst[1] = component(5);
+        dst[2] = component(10);
+        if let Some(alpha) = dst.get_mut(3) {
+            *alpha = if v & 0x8000 != 0 { 255 } else { 0 };
+        }
     } else {
-        Err(TGAError::IncorrectFileLayout)
+        dst.copy_from_slice(&src[..dst.len()]);
     }
 }
 
@@ -280,6 +398,56 @@ fn unload_rle_data<W: Write>(img: &TGAImage, dst: &mut W) -> TGAResult<()> {
     Ok(())
 }
 
+/// How [`TGAImage::write_tga`] stores the image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TGAWriteOptions {
+    /// Run-length encode the pixels (types 10 and 11), otherwise they are
+    /// stored raw (types 2 and 3).
+    pub rle: bool,
+    /// Append the TGA 2.0 footer.
+    pub footer: bool,
+}
+
+impl Default for TGAWriteOptions {
+    fn default() -> Self {
+        TGAWriteOptions { rle: true, footer: true }
+    }
+}
+
+/// Limits of [`TGAImage::read_tga_with`] and of the readers of the other
+/// formats, checked before the image is allocated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TGAReadOptions {
+    /// Largest width times height of the images read, larger ones fail with
+    /// [`TGAError::BadHeader`]. A compressed file of a few bytes may have a
+    /// header of a huge image.
+    pub max_pixels: usize,
+}
+
+impl TGAReadOptions {
+    pub const DEFAULT_MAX_PIXELS: usize = 1 << 26;
+
+    /// Bytes of the pixels of a `w` by `h` image with `bpp` bytes per pixel,
+    /// `None` if the image is over the limit or they overflow the `i32`
+    /// sizes of [`TGAImage`].
+    fn data_size(&self, w: usize, h: usize, bpp: usize) -> Option<usize> {
+        let pixels = w.checked_mul(h).filter(|&pixels| pixels <= self.max_pixels)?;
+        pixels.checked_mul(bpp).filter(|&size| size <= i32::MAX as usize)
+    }
+}
+
+impl Default for TGAReadOptions {
+    fn default() -> Self {
+        TGAReadOptions { max_pixels: Self::DEFAULT_MAX_PIXELS }
+    }
+}
+
+impl Default for TGAImage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TGAImage {
     const FOOTER: &str = "\0\0\0\0\0\0\0\0TRUEVISION-XFILE.\0";
 
@@ -288,7 +456,8 @@ impl TGAImage {
             data: Vec::new(),
             width: 0,
             height: 0,
-            bytespp: 0
+            bytespp: 0,
+            origin: TGAOrigin::BottomLeft,
         }
     }
 
@@ -299,60 +468,177 @@ impl TGAImage {
             data,
             width: w,
             height: h,
-            bytespp: bpp
+            bytespp: bpp,
+            origin: TGAOrigin::BottomLeft,
+        }
+    }
+
+    /// Corner of the picture pixel (0, 0) is at.
+    pub fn origin(&self) -> TGAOrigin {
+        self.origin
+    }
+
+    /// Reorder the pixels in memory to start at the `origin` corner, the
+    /// picture stays the same.
+    pub fn set_origin(&mut self, origin: TGAOrigin) -> TGAResult<()> {
+        if self.origin.is_top() != origin.is_top() {
+            self.flip_vertically()?;
+        }
+        if self.origin.is_right() != origin.is_right() {
+            self.flip_horizontally()?;
         }
+        self.origin = origin;
+        Ok(())
+    }
+
+    /// Bytes per pixel, one of the [`tga_format`] constants.
+    pub fn bytespp(&self) -> i32 {
+        self.bytespp
     }
 
     pub fn from_tga_file(filename: &str) -> TGAResult<Self> {
         if let Ok(file) = File::open(filename) {
-            let mut buffered_file = BufReader::new(file);
-            let header = TGAHeader::read(&mut buffered_file)?;
-            let bytespp = header.bits_per_pixel as i32 / 8;
-            if header.width <= 0 || header.height <= 0 || (
-                bytespp != tga_format::GRAYSCALE &&
-                bytespp != tga_format::RGB &&
-                bytespp != tga_format::RGBA) {
-                return Err(TGAError::BadHeader)
+            Self::read_tga(&mut BufReader::new(file))
+        } else {
+            Err(TGAError::FileOpenError)
+        }
+    }
+
+    /// Read a TGA image of any of the standard types: color-mapped (1 and
+    /// 9), true-color (2 and 10) and grayscale (3 and 11), raw or RLE.
+    /// Color-mapped and 15/16 bit images are converted to RGB or RGBA, and
+    /// the pixels are reordered to the bottom left origin. The data after
+    /// the pixels, e.g. the TGA 2.0 footer, is not read.
+    pub fn read_tga<R: Read>(src: &mut R) -> TGAResult<Self> {
+        Self::read_tga_with(src, TGAReadOptions::default())
+    }
+
+    pub fn read_tga_with<R: Read>(src: &mut R, options: TGAReadOptions) -> TGAResult<Self> {
+        let header = TGAHeader::read(src)?;
+        if header.width <= 0 || header.height <= 0 {
+            return Err(TGAError::BadHeader)
+        }
+        let depth = header.bits_per_pixel as u8;
+        let alpha_bits = header.image_descriptor as u8 & 0x0F;
+
+        let mut id = vec![0u8; header.id_length as u8 as usize];
+        src.read_exact(&mut id)?;
+
+        // the color map may be present even if the pixels don't use it
+        let mut color_map = vec![];
+        if header.color_map_type == 1 {
+            let entry_size = (header.color_map_depth as u8 as usize).div_ceil(8);
+            color_map = vec![0u8; header.color_map_length.max(0) as usize * entry_size];
+            src.read_exact(&mut color_map)?;
+        } else if header.color_map_type != 0 {
+            return Err(TGAError::BadHeader)
+        }
+
+        let mapped = matches!(header.data_type_code, 1 | 9);
+        let bytespp = match header.data_type_code {
+            1 | 9 if header.color_map_type == 1 && (depth == 8 || depth == 16) => {
+                color_bytespp(header.color_map_depth as u8, alpha_bits)
+            },
+            2 | 10 => color_bytespp(depth, alpha_bits),
+            3 | 11 if depth == 8 => Some(tga_format::GRAYSCALE),
+            _ => None,
+        }.ok_or(TGAError::BadHeader)?;
+        if options.data_size(header.width as usize, header.height as usize, bytespp as usize).is_none() {
+            return Err(TGAError::BadHeader)
+        }
+
+        let pixel_size = (depth as usize).div_ceil(8);
+        let pixels_size = (header.width as usize) * (header.height as usize) * pixel_size;
+        let pixels = if header.data_type_code & 8 != 0 {
+            load_rle_data(src, pixels_size, pixel_size)?
+        } else {
+            let mut pixels = vec![];
+            src.take(pixels_size as u64).read_to_end(&mut pixels)?;
+            if pixels.len() < pixels_size {
+                return Err(TGAError::IncorrectFileLayout)
             }
-            let mut image = Self::with_size(header.width.into(),
-                                            header.height.into(),
-                                            bytespp);
-            load_rle_data(&mut buffered_file, &mut image)?;
-
-            let mut buf = [0u8; Self::FOOTER.len()];
-            let footer_read_res = buffered_file.read(&mut buf);
-            if let Ok(_) = footer_read_res {
-                if buf == Self::FOOTER.as_bytes() {
-                    Ok(image)
-                } else {
-                    Err(TGAError::IncorrectFileLayout)
-                }
-            } else {
-                Err(TGAError::IncorrectFileLayout)
+            pixels
+        };
+
+        let mut image = Self::with_size(header.width.into(), header.height.into(), bytespp);
+        let palette: Vec<u8> = if mapped {
+            let entry_size = (header.color_map_depth as u8 as usize).div_ceil(8);
+            let mut palette = vec![0u8; color_map.len() / entry_size * bytespp as usize];
+            for (entry, dst) in color_map.chunks_exact(entry_size).zip(palette.chunks_exact_mut(bytespp as usize)) {
+                convert_pixel(entry, header.color_map_depth as u8, dst);
             }
+            palette
         } else {
-            Err(TGAError::FileOpenError)
+            vec![]
+        };
+        let bpp = bytespp as usize;
+        for (src, dst) in pixels.chunks_exact(pixel_size).zip(image.data.chunks_exact_mut(bpp)) {
+            if mapped {
+                let index = if pixel_size == 1 { src[0] as usize } else { u16::from_le_bytes([src[0], src[1]]) as usize };
+                let entry = index.checked_sub(header.color_map_origin.max(0) as usize)
+                                 .and_then(|i| palette.get(i * bpp..(i + 1) * bpp))
+                                 .ok_or(TGAError::IncorrectFileLayout)?;
+                dst.copy_from_slice(entry);
+            } else {
+                convert_pixel(src, depth, dst);
+            }
         }
+        image.origin = TGAOrigin::from_descriptor(header.image_descriptor as u8);
+        image.set_origin(TGAOrigin::BottomLeft)?;
+        Ok(image)
     }
 
+    /// Write the image RLE compressed with the TGA 2.0 footer.
     pub fn write_to_file(&self, filename: &str) -> TGAResult<()> {
+        self.write_to_file_with(filename, TGAWriteOptions::default())
+    }
+
+    pub fn write_to_file_with(&self, filename: &str, options: TGAWriteOptions) -> TGAResult<()> {
         if let Ok(file) = File::create(filename) {
             let mut buffered_file = BufWriter::new(file);
-            let header = TGAHeader::from_image(self);
-            header.write(&mut buffered_file)?;
-            unload_rle_data(self, &mut buffered_file)?;
-            buffered_file.write_all(Self::FOOTER.as_bytes())?;
+            self.write_tga(&mut buffered_file, options)?;
+            buffered_file.flush()?;
+            Ok(())
         } else {
-            return Err(TGAError::FileOpenError)
+            Err(TGAError::FileOpenError)
         }
+    }
 
+    pub fn write_tga<W: Write>(&self, dst: &mut W, options: TGAWriteOptions) -> TGAResult<()> {
+        if self.data.is_empty() {
+            return Err(TGAError::EmptyImage)
+        }
+        TGAHeader::from_image(self, options.rle).write(dst)?;
+        if options.rle {
+            unload_rle_data(self, dst)?;
+        } else {
+            dst.write_all(&self.data)?;
+        }
+        if options.footer {
+            dst.write_all(Self::FOOTER.as_bytes())?;
+        }
         Ok(())
     }
 
-    // fn flip_horizontally(self: &mut Self) -> bool {
+    /// Mirror the picture left to right.
+    pub fn flip_horizontally(&mut self) -> TGAResult<()> {
+        if self.data.is_empty() {
+            return Err(TGAError::EmptyImage)
+        }
 
-    // }
+        let bpp = self.bytespp as usize;
+        for line in self.data.chunks_exact_mut(self.width as usize * bpp) {
+            let width = self.width as usize;
+            for i in 0..width / 2 {
+                let (left, right) = line.split_at_mut((width - 1 - i) * bpp);
+                left[i * bpp..(i + 1) * bpp].swap_with_slice(&mut right[..bpp]);
+            }
+        }
 
+        Ok(())
+    }
+
+    /// Mirror the picture upside down.
     pub fn flip_vertically(&mut self) -> TGAResult<()> {
         if self.data.is_empty() {
             return Err(TGAError::EmptyImage)
@@ -443,7 +729,20 @@ impl TGAImage {
 
 #[cfg(test)]
 mod tests {
-    use super::{tga_format, TGAColor, TGAImage, unload_rle_data};
+    use super::{tga_format, TGAColor, TGAError, TGAImage, TGAOrigin, TGAReadOptions, TGAWriteOptions, unload_rle_data};
+
+    /// TGA header with the given type, color map and pixel layout.
+    fn header(data_type: u8, color_map: (u16, u16, u8), width: u16, height: u16, depth: u8, descriptor: u8) -> Vec<u8> {
+        let (origin, length, map_depth) = color_map;
+        let mut res = vec![0, (length > 0) as u8, data_type];
+        res.extend(origin.to_le_bytes());
+        res.extend(length.to_le_bytes());
+        res.extend([map_depth, 0, 0, 0, 0]);
+        res.extend(width.to_le_bytes());
+        res.extend(height.to_le_bytes());
+        res.extend([depth, descriptor]);
+        res
+    }
 
     #[test]
     fn rle_encode_grayscale() {
@@ -451,7 +750,8 @@ mod tests {
             data: vec![0, 3, 1, 4, 4, 5, 5, 5, 0],
             width: 3,
             height: 3,
-            bytespp: tga_format::GRAYSCALE
+            bytespp: tga_format::GRAYSCALE,
+            origin: TGAOrigin::BottomLeft,
         };
 
         let mut target = vec![0_u8; 10];
@@ -471,7 +771,8 @@ mod tests {
             ],
             width: 3,
             height: 3,
-            bytespp: tga_format::RGB
+            bytespp: tga_format::RGB,
+            origin: TGAOrigin::BottomLeft,
         };
 
         let mut target = vec![0_u8; 12];
@@ -487,7 +788,8 @@ mod tests {
             data: vec![1, 2, 3, 4, 5, 6],
             width: 3,
             height: 2,
-            bytespp: tga_format::GRAYSCALE
+            bytespp: tga_format::GRAYSCALE,
+            origin: TGAOrigin::BottomLeft,
         };
 
         image.flip_vertically().unwrap();
@@ -513,7 +815,8 @@ mod tests {
             ],
             width: 2,
             height: 3,
-            bytespp: tga_format::RGB
+            bytespp: tga_format::RGB,
+            origin: TGAOrigin::BottomLeft,
         };
 
         let color = image.get(1, 2).unwrap();
@@ -521,4 +824,121 @@ mod tests {
         assert_eq!(color.g(), 0);
         assert_eq!(color.b(), 128);
     }
+
+    #[test]
+    fn read_raw_true_color_skipping_image_id() {
+        let mut file = header(2, (0, 0, 0), 2, 1, 24, 0);
+        file[0] = 2;
+        file.extend(b"id");
+        file.extend([1, 2, 3, 4, 5, 6]);
+        let image = TGAImage::read_tga(&mut file.as_slice()).unwrap();
+        assert_eq!(image.bytespp(), tga_format::RGB);
+        assert_eq!(image.get(0, 0).unwrap(), TGAColor::from_rgb(3, 2, 1));
+        assert_eq!(image.get(1, 0).unwrap(), TGAColor::from_rgb(6, 5, 4));
+    }
+
+    #[test]
+    fn reject_oversized_header() {
+        let file = header(2, (0, 0, 0), 32767, 32767, 32, 8);
+        assert!(matches!(TGAImage::read_tga(&mut file.as_slice()), Err(TGAError::BadHeader)));
+
+        let mut rle = header(11, (0, 0, 0), 8000, 8000, 8, 0);
+        rle.extend([0xFF, 0]);
+        assert!(matches!(TGAImage::read_tga(&mut rle.as_slice()), Err(TGAError::IOError)));
+        let options = TGAReadOptions { max_pixels: 1000 };
+        assert!(matches!(TGAImage::read_tga_with(&mut rle.as_slice(), options), Err(TGAError::BadHeader)));
+    }
+
+    #[test]
+    fn read_color_mapped() {
+        // two BGR entries starting at index 2
+        let color_map = [10, 20, 30, 40, 50, 60];
+        let dark = TGAColor::from_rgb(30, 20, 10);
+        let light = TGAColor::from_rgb(60, 50, 40);
+
+        let mut raw = header(1, (2, 2, 24), 3, 1, 8, 0);
+        raw.extend(color_map);
+        raw.extend([3, 2, 3]);
+        let image = TGAImage::read_tga(&mut raw.as_slice()).unwrap();
+        assert_eq!([0, 1, 2].map(|x| image.get(x, 0).unwrap()), [light, dark, light]);
+
+        let mut rle = header(9, (2, 2, 24), 3, 1, 8, 0);
+        rle.extend(color_map);
+        rle.extend([0x81, 2, 0x00, 3]);
+        let image = TGAImage::read_tga(&mut rle.as_slice()).unwrap();
+        assert_eq!([0, 1, 2].map(|x| image.get(x, 0).unwrap()), [dark, dark, light]);
+
+        // index outside of the color map
+        *raw.last_mut().unwrap() = 5;
+        assert!(matches!(TGAImage::read_tga(&mut raw.as_slice()), Err(TGAError::IncorrectFileLayout)));
+    }
+
+    #[test]
+    fn read_16_bit_pixels() {
+        let mut file = header(2, (0, 0, 0), 2, 1, 16, 1);
+        file.extend(0x801Fu16.to_le_bytes());
+        file.extend(0x7C00u16.to_le_bytes());
+        let image = TGAImage::read_tga(&mut file.as_slice()).unwrap();
+        assert_eq!(image.get(0, 0).unwrap(), TGAColor::from_rgba(0, 0, 255, 255));
+        assert_eq!(image.get(1, 0).unwrap(), TGAColor::from_rgba(255, 0, 0, 0));
+
+        // without the alpha bit
+        file[17] = 0;
+        let image = TGAImage::read_tga(&mut file.as_slice()).unwrap();
+        assert_eq!(image.get(0, 0).unwrap(), TGAColor::from_rgb(0, 0, 255));
+    }
+
+    #[test]
+    fn write_raw_and_rle() {
+        for bytespp in [tga_format::GRAYSCALE, tga_format::RGB, tga_format::RGBA] {
+            let mut image = TGAImage::with_size(7, 5, bytespp);
+            for (i, v) in image.data.iter_mut().enumerate() {
+                *v = (i / 5 * 37 % 256) as u8;
+            }
+            for rle in [false, true] {
+                let mut file = vec![];
+                image.write_tga(&mut file, TGAWriteOptions { rle, footer: false }).unwrap();
+                let data_type = match bytespp { tga_format::GRAYSCALE => 3, _ => 2 } + if rle { 8 } else { 0 };
+                assert_eq!(file[2], data_type);
+                if !rle {
+                    assert_eq!(file.len(), 18 + image.data.len());
+                }
+                assert_eq!(TGAImage::read_tga(&mut file.as_slice()).unwrap(), image, "{} {}", bytespp, rle);
+            }
+        }
+    }
+
+    #[test]
+    fn flip_horizontally() {
+        let mut image = TGAImage {
+            data: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
+            width: 2,
+            height: 2,
+            bytespp: tga_format::RGB,
+            origin: TGAOrigin::BottomLeft,
+        };
+
+        image.flip_horizontally().unwrap();
+
+        let expected = [4, 5, 6, 1, 2, 3, 10, 11, 12, 7, 8, 9] as [u8; 12];
+        assert_eq!(image.data.as_slice(), expected);
+    }
+
+    #[test]
+    fn origin_is_normalized_on_load_and_kept_on_write() {
+        // 2x2 grayscale, rows from the top and pixels from the right
+        let mut file = header(3, (0, 0, 0), 2, 2, 8, 0x30);
+        file.extend([1, 2, 3, 4]);
+        let mut image = TGAImage::read_tga(&mut file.as_slice()).unwrap();
+        assert_eq!(image.origin(), TGAOrigin::BottomLeft);
+        assert_eq!(image.data, [4, 3, 2, 1]);
+
+        image.set_origin(TGAOrigin::TopLeft).unwrap();
+        assert_eq!(image.data, [2, 1, 4, 3]);
+        let mut written = vec![];
+        image.write_tga(&mut written, TGAWriteOptions { rle: false, footer: false }).unwrap();
+        assert_eq!(written[17], 0x20);
+        assert_eq!(&written[18..], [2, 1, 4, 3]);
+        assert_eq!(TGAImage::read_tga(&mut written.as_slice()).unwrap().data, [4, 3, 2, 1]);
+    }
 }
@@ -0,0 +1,373 @@
+//! BMP reading and writing of [`TGAImage`].
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use super::{tga_format, TGAError, TGAImage, TGAOrigin, TGAReadOptions, TGAResult};
+
+const FILE_HEADER_SIZE: usize = 14;
+const INFO_HEADER_SIZE: usize = 40;
+const V4_HEADER_SIZE: usize = 108;
+
+const BI_RGB: u32 = 0;
+const BI_RLE8: u32 = 1;
+const BI_BITFIELDS: u32 = 3;
+
+fn u16_at(bytes: &[u8], pos: usize) -> TGAResult<u16> {
+    let b = bytes.get(pos..pos + 2).ok_or(TGAError::IncorrectFileLayout)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_at(bytes: &[u8], pos: usize) -> TGAResult<u32> {
+    let b = bytes.get(pos..pos + 4).ok_or(TGAError::IncorrectFileLayout)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Bytes of a pixel row, padded to a multiple of 4.
+fn stride(width: usize, bits: usize) -> usize {
+    (width * bits).div_ceil(32) * 4
+}
+
+/// Expand the RLE8 encoded indices to one byte per pixel, rows from the
+/// bottom. Pixels skipped by deltas or missing at the end of the lines
+/// keep index 0.
+fn load_rle8_data(src: &[u8], width: usize, height: usize) -> TGAResult<Vec<u8>> {
+    let mut indices = vec![0u8; width * height];
+    let (mut x, mut y) = (0, 0);
+    let mut pos = 0;
+    let mut put = |x: &mut usize, y: usize, index: u8| -> TGAResult<()> {
+        if *x >= width || y >= height {
+            return Err(TGAError::IncorrectFileLayout)
+        }
+        indices[y * width + *x] = index;
+        *x += 1;
+        Ok(())
+    };
+    loop {
+        let pair = src.get(pos..pos + 2).ok_or(TGAError::IncorrectFileLayout)?;
+        let (count, value) = (pair[0] as usize, pair[1]);
+        pos += 2;
+        match (count, value) {
+            (0, 0) => {
+                x = 0;
+                y += 1;
+            },
+            (0, 1) => break,
+            (0, 2) => {
+                let delta = src.get(pos..pos + 2).ok_or(TGAError::IncorrectFileLayout)?;
+                x += delta[0] as usize;
+                y += delta[1] as usize;
+                pos += 2;
+            },
+            (0, n) => {
+                // absolute run, padded to an even number of bytes
+                let n = n as usize;
+                let run = src.get(pos..pos + n).ok_or(TGAError::IncorrectFileLayout)?;
+                for &index in run {
+                    put(&mut x, y, index)?;
+                }
+                pos += n + n % 2;
+            },
+            (count, index) => {
+                for _ in 0..count {
+                    put(&mut x, y, index)?;
+                }
+            },
+        }
+    }
+    Ok(indices)
+}
+
+impl TGAImage {
+    pub fn from_bmp_file(filename: &str) -> TGAResult<Self> {
+        if let Ok(file) = File::open(filename) {
+            Self::read_bmp(&mut BufReader::new(file))
+        } else {
+            Err(TGAError::FileOpenError)
+        }
+    }
+
+    /// Read an uncompressed 24 or 32 bit, an 8 bit palettized or an RLE8
+    /// compressed BMP image. 24 bit and palettized images become `RGB`
+    /// ones and 32 bit images `RGBA` ones, opaque if none of their pixels
+    /// has alpha. The rows are reordered to the bottom left origin.
+    pub fn read_bmp<R: Read>(src: &mut R) -> TGAResult<Self> {
+        Self::read_bmp_with(src, TGAReadOptions::default())
+    }
+
+    pub fn read_bmp_with<R: Read>(src: &mut R, options: TGAReadOptions) -> TGAResult<Self> {
+        let mut bytes = vec![];
+        src.read_to_end(&mut bytes)?;
+        if bytes.len() < FILE_HEADER_SIZE + 12 || &bytes[..2] != b"BM" {
+            return Err(TGAError::BadHeader)
+        }
+        let data_offset = u32_at(&bytes, 10)? as usize;
+
+        let info = FILE_HEADER_SIZE;
+        let info_size = u32_at(&bytes, info)? as usize;
+        let (width, height, bits, compression, colors_used) = if info_size == 12 {
+            // OS/2 core header
+            (u16_at(&bytes, info + 4)? as i64, u16_at(&bytes, info + 6)? as i16 as i64,
+             u16_at(&bytes, info + 10)?, BI_RGB, 0)
+        } else if info_size >= INFO_HEADER_SIZE {
+            (u32_at(&bytes, info + 4)? as i32 as i64, u32_at(&bytes, info + 8)? as i32 as i64,
+             u16_at(&bytes, info + 14)?, u32_at(&bytes, info + 16)?, u32_at(&bytes, info + 32)? as usize)
+        } else {
+            return Err(TGAError::BadHeader)
+        };
+        let supported = matches!((bits, compression), (8, BI_RGB) | (8, BI_RLE8) | (24, BI_RGB) | (32, BI_RGB));
+        // bit fields are only supported in the usual BGRA layout
+        let bitfields = bits == 32 && compression == BI_BITFIELDS && info_size >= INFO_HEADER_SIZE && {
+            // after the info header, inside the larger ones
+            let masks = info + INFO_HEADER_SIZE;
+            (u32_at(&bytes, masks)?, u32_at(&bytes, masks + 4)?, u32_at(&bytes, masks + 8)?) ==
+                (0x00FF_0000, 0x0000_FF00, 0x0000_00FF)
+        };
+        let top_down = height < 0;
+        let (width, height) = (width as usize, height.unsigned_abs() as usize);
+        let bytespp = if bits == 8 { tga_format::RGB as usize } else { bits as usize / 8 };
+        if !(supported || bitfields) || width == 0 || height == 0 ||
+           options.data_size(width, height, bytespp).is_none() || (top_down && compression == BI_RLE8) {
+            return Err(TGAError::BadHeader)
+        }
+        // check the uncompressed rows are there before allocating the image
+        let row_size = stride(width, bits as usize);
+        if compression != BI_RLE8 && row_size.checked_mul(height).and_then(|size| size.checked_add(data_offset))
+                                              .is_none_or(|end| end > bytes.len()) {
+            return Err(TGAError::IncorrectFileLayout)
+        }
+
+        let mut image;
+        if bits == 8 {
+            let entry_size = if info_size == 12 { 3 } else { 4 };
+            let palette_len = if colors_used == 0 || colors_used > 256 { 256 } else { colors_used };
+            let palette_start = info + info_size;
+            let palette_end = (palette_start + palette_len * entry_size).min(data_offset);
+            let palette = bytes.get(palette_start..palette_end).ok_or(TGAError::IncorrectFileLayout)?;
+
+            let pixels = bytes.get(data_offset..).ok_or(TGAError::IncorrectFileLayout)?;
+            let (indices, row_size) = if compression == BI_RLE8 {
+                (load_rle8_data(pixels, width, height)?, width)
+            } else {
+                (pixels.to_vec(), row_size)
+            };
+            image = TGAImage::with_size(width as i32, height as i32, tga_format::RGB);
+            for (y, dst_row) in image.data.chunks_exact_mut(width * 3).enumerate() {
+                let row = indices.get(y * row_size..y * row_size + width).ok_or(TGAError::IncorrectFileLayout)?;
+                for (&index, dst) in row.iter().zip(dst_row.chunks_exact_mut(3)) {
+                    let entry = palette.get(index as usize * entry_size..index as usize * entry_size + 3)
+                        .ok_or(TGAError::IncorrectFileLayout)?;
+                    dst.copy_from_slice(entry);
+                }
+            }
+        } else {
+            image = TGAImage::with_size(width as i32, height as i32, bytespp as i32);
+            for (y, dst_row) in image.data.chunks_exact_mut(width * bytespp).enumerate() {
+                let start = data_offset + y * row_size;
+                dst_row.copy_from_slice(&bytes[start..start + width * bytespp]);
+            }
+            // the fourth byte is often left unused
+            if bytespp == 4 && image.data.chunks_exact(4).all(|pixel| pixel[3] == 0) {
+                image.data.chunks_exact_mut(4).for_each(|pixel| pixel[3] = 255);
+            }
+        }
+        if top_down {
+            image.origin = TGAOrigin::TopLeft;
+            image.set_origin(TGAOrigin::BottomLeft)?;
+        }
+        Ok(image)
+    }
+
+    pub fn write_bmp_file(&self, filename: &str) -> TGAResult<()> {
+        if let Ok(file) = File::create(filename) {
+            let mut buffered_file = BufWriter::new(file);
+            self.write_bmp(&mut buffered_file)?;
+            buffered_file.flush()?;
+            Ok(())
+        } else {
+            Err(TGAError::FileOpenError)
+        }
+    }
+
+    /// Write the image uncompressed, RGBA images as 32 bit with a version 4
+    /// header giving the alpha mask and the others as 24 bit.
+    pub fn write_bmp<W: Write>(&self, dst: &mut W) -> TGAResult<()> {
+        if self.data.is_empty() {
+            return Err(TGAError::EmptyImage)
+        }
+        let alpha = self.bytespp == tga_format::RGBA;
+        let (bits, info_size, compression) = if alpha {
+            (32, V4_HEADER_SIZE, BI_BITFIELDS)
+        } else {
+            (24, INFO_HEADER_SIZE, BI_RGB)
+        };
+        let row_size = stride(self.width as usize, bits);
+        let data_size = row_size * self.height as usize;
+        let data_offset = FILE_HEADER_SIZE + info_size;
+
+        let mut header = Vec::with_capacity(data_offset);
+        header.extend_from_slice(b"BM");
+        header.extend_from_slice(&((data_offset + data_size) as u32).to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        header.extend_from_slice(&(data_offset as u32).to_le_bytes());
+
+        header.extend_from_slice(&(info_size as u32).to_le_bytes());
+        header.extend_from_slice(&self.width.to_le_bytes());
+        header.extend_from_slice(&self.height.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&(bits as u16).to_le_bytes());
+        header.extend_from_slice(&compression.to_le_bytes());
+        header.extend_from_slice(&(data_size as u32).to_le_bytes());
+        // 72 DPI, no palette
+        header.extend_from_slice(&2835u32.to_le_bytes());
+        header.extend_from_slice(&2835u32.to_le_bytes());
+        header.extend_from_slice(&[0; 8]);
+        if alpha {
+            for mask in [0x00FF_0000u32, 0x0000_FF00, 0x0000_00FF, 0xFF00_0000] {
+                header.extend_from_slice(&mask.to_le_bytes());
+            }
+            // sRGB color space, the end points and gamma are ignored
+            header.extend_from_slice(b"BGRs");
+            header.extend_from_slice(&[0; 48]);
+        }
+        dst.write_all(&header)?;
+
+        // rows go from the bottom in BMP files
+        let mut image = self.clone();
+        image.set_origin(TGAOrigin::BottomLeft)?;
+        let mut row = vec![0u8; row_size];
+        for src_row in image.data.chunks_exact(self.width as usize * self.bytespp as usize) {
+            for (src, dst) in src_row.chunks_exact(self.bytespp as usize).zip(row.chunks_exact_mut(bits / 8)) {
+                if self.bytespp == tga_format::GRAYSCALE {
+                    dst.fill(src[0]);
+                } else {
+                    dst.copy_from_slice(src);
+                }
+            }
+            dst.write_all(&row)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tgaimage::{tga_format, TGAColor, TGAError, TGAImage, TGAReadOptions};
+
+    /// File and info headers of a BMP with `data_offset` bytes before the
+    /// pixels.
+    fn header(width: i32, height: i32, bits: u16, compression: u32, colors: u32, data_offset: u32) -> Vec<u8> {
+        let mut bytes = b"BM".to_vec();
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&data_offset.to_le_bytes());
+        bytes.extend_from_slice(&40u32.to_le_bytes());
+        bytes.extend_from_slice(&width.to_le_bytes());
+        bytes.extend_from_slice(&height.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&bits.to_le_bytes());
+        bytes.extend_from_slice(&compression.to_le_bytes());
+        bytes.extend_from_slice(&[0; 12]);
+        bytes.extend_from_slice(&colors.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes
+    }
+
+    /// Black, red, green and blue entries.
+    const PALETTE: [u8; 16] = [0, 0, 0, 0, 0, 0, 255, 0, 0, 255, 0, 0, 255, 0, 0, 0];
+
+    #[test]
+    fn read_palettized() {
+        // 3x2, rows padded to 4 bytes, bottom row first
+        let mut file = header(3, 2, 8, 0, 4, 14 + 40 + 16);
+        file.extend_from_slice(&PALETTE);
+        file.extend_from_slice(&[1, 2, 3, 0, 3, 3, 0, 0]);
+        let image = TGAImage::read_bmp(&mut file.as_slice()).unwrap();
+        assert_eq!(image.bytespp(), tga_format::RGB);
+        let bottom = [0, 1, 2].map(|x| image.get(x, 0).unwrap());
+        assert_eq!(bottom, [TGAColor::from_rgb(255, 0, 0), TGAColor::from_rgb(0, 255, 0), TGAColor::from_rgb(0, 0, 255)]);
+        assert_eq!(image.get(0, 1).unwrap(), TGAColor::from_rgb(0, 0, 255));
+        assert_eq!(image.get(2, 1).unwrap(), TGAColor::from_rgb(0, 0, 0));
+    }
+
+    #[test]
+    fn read_rle8() {
+        let mut file = header(4, 3, 8, 1, 4, 14 + 40 + 16);
+        file.extend_from_slice(&PALETTE);
+        file.extend_from_slice(&[
+            4, 1, 0, 0, // four red pixels
+            0, 3, 2, 3, 1, 0, // absolute run, padded
+            0, 2, 0, 1, // delta to (3, 2)
+            1, 3, 0, 1,
+        ]);
+        let image = TGAImage::read_bmp(&mut file.as_slice()).unwrap();
+        let row = |y: i32| [0, 1, 2, 3].map(|x| image.get(x, y).unwrap().r());
+        let (black, red) = (0, 255);
+        assert_eq!(row(0), [red; 4]);
+        assert_eq!(image.get(0, 1).unwrap(), TGAColor::from_rgb(0, 255, 0));
+        assert_eq!(image.get(1, 1).unwrap(), TGAColor::from_rgb(0, 0, 255));
+        assert_eq!(row(1)[2..], [red, black]);
+        assert_eq!(image.get(3, 2).unwrap(), TGAColor::from_rgb(0, 0, 255));
+        assert_eq!(row(2)[..3], [black; 3]);
+
+        // runs must stay inside the image
+        let last = file.len() - 4;
+        file[last] = 3;
+        assert!(TGAImage::read_bmp(&mut file.as_slice()).is_err());
+    }
+
+    #[test]
+    fn read_top_down_24_bit() {
+        let mut file = header(1, -2, 24, 0, 0, 14 + 40);
+        file.extend_from_slice(&[255, 0, 0, 0, 0, 0, 255, 0]);
+        let image = TGAImage::read_bmp(&mut file.as_slice()).unwrap();
+        assert_eq!(image.get(0, 1).unwrap(), TGAColor::from_rgb(0, 0, 255));
+        assert_eq!(image.get(0, 0).unwrap(), TGAColor::from_rgb(255, 0, 0));
+    }
+
+    #[test]
+    fn reject_oversized_headers() {
+        let oversized = header(65536, 65536, 24, 0, 0, 14 + 40);
+        assert!(matches!(TGAImage::read_bmp(&mut oversized.as_slice()), Err(TGAError::BadHeader)));
+        let mut oversized_rle = header(65536, 65536, 8, 1, 4, 14 + 40 + 16);
+        oversized_rle.extend_from_slice(&PALETTE);
+        oversized_rle.extend_from_slice(&[0, 1]);
+        assert!(matches!(TGAImage::read_bmp(&mut oversized_rle.as_slice()), Err(TGAError::BadHeader)));
+        // the end of the bitmap right away is a valid image of any size
+        let mut rle = header(100, 100, 8, 1, 4, 14 + 40 + 16);
+        rle.extend_from_slice(&PALETTE);
+        rle.extend_from_slice(&[0, 1]);
+        assert_eq!(TGAImage::read_bmp(&mut rle.as_slice()).unwrap().width, 100);
+        let options = TGAReadOptions { max_pixels: 1000 };
+        assert!(matches!(TGAImage::read_bmp_with(&mut rle.as_slice(), options), Err(TGAError::BadHeader)));
+
+        // fits in memory but the file is far too short
+        let mut truncated = header(4000, 4000, 32, 0, 0, 14 + 40);
+        truncated.extend_from_slice(&[0; 16]);
+        assert!(matches!(TGAImage::read_bmp(&mut truncated.as_slice()), Err(TGAError::IncorrectFileLayout)));
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        for bytespp in [tga_format::GRAYSCALE, tga_format::RGB, tga_format::RGBA] {
+            let mut image = TGAImage::with_size(5, 3, bytespp);
+            for y in 0..3 {
+                for x in 0..5 {
+                    let v = |k: i32| (x * k + y * 31) as u8;
+                    image.set(x, y, TGAColor::from_rgba(v(7), v(3), v(11), v(5))).unwrap();
+                }
+            }
+            let mut file = vec![];
+            image.write_bmp(&mut file).unwrap();
+            let read_back = TGAImage::read_bmp(&mut file.as_slice()).unwrap();
+            if bytespp == tga_format::GRAYSCALE {
+                // stored as 24 bit
+                assert_eq!(read_back.bytespp(), tga_format::RGB);
+                let v = image.get(4, 2).unwrap().b();
+                assert_eq!(read_back.get(4, 2).unwrap(), TGAColor::from_rgb(v, v, v));
+            } else {
+                assert_eq!(read_back, image, "{}", bytespp);
+            }
+        }
+    }
+}
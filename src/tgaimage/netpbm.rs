@@ -0,0 +1,331 @@
+//! Netpbm (PGM, PPM and PAM) reading and writing of [`TGAImage`].
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use super::{tga_format, TGAError, TGAImage, TGAOrigin, TGAReadOptions, TGAResult};
+
+/// How [`TGAImage::write_pnm`] stores the image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NetpbmFormat {
+    /// Decimal samples, P2 for grayscale images and P3 for color ones.
+    Plain,
+    /// Binary samples, P5 for grayscale images and P6 for color ones.
+    #[default]
+    Binary,
+    /// P7 keeping the alpha of RGBA images.
+    Pam,
+}
+
+/// Header fields and samples of a Netpbm file.
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace_and_comments(&mut self) {
+        while let Some(&c) = self.bytes.get(self.pos) {
+            if c == b'#' {
+                while self.bytes.get(self.pos).is_some_and(|&c| c != b'\n') {
+                    self.pos += 1;
+                }
+            } else if c.is_ascii_whitespace() {
+                self.pos += 1;
+            } else {
+                break
+            }
+        }
+    }
+
+    fn token(&mut self) -> TGAResult<&'a str> {
+        self.skip_whitespace_and_comments();
+        let start = self.pos;
+        while self.bytes.get(self.pos).is_some_and(|c| !c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+        match std::str::from_utf8(&self.bytes[start..self.pos]) {
+            Ok(token) if !token.is_empty() => Ok(token),
+            _ => Err(TGAError::IncorrectFileLayout),
+        }
+    }
+
+    fn number(&mut self) -> TGAResult<usize> {
+        self.token()?.parse().map_err(|_| TGAError::IncorrectFileLayout)
+    }
+
+    /// Skip the rest of the line, the value of PAM header lines may
+    /// contain spaces.
+    fn skip_line(&mut self) {
+        while self.bytes.get(self.pos).is_some_and(|&c| c != b'\n') {
+            self.pos += 1;
+        }
+    }
+
+    /// Skip the single whitespace character ending the header of binary
+    /// formats.
+    fn end_header(&mut self) -> TGAResult<()> {
+        match self.bytes.get(self.pos) {
+            Some(c) if c.is_ascii_whitespace() => {
+                self.pos += 1;
+                Ok(())
+            },
+            _ => Err(TGAError::IncorrectFileLayout),
+        }
+    }
+
+    /// Next binary sample, two bytes big-endian when `maxval` exceeds 255.
+    fn binary_sample(&mut self, maxval: usize) -> TGAResult<usize> {
+        let size = if maxval > 255 { 2 } else { 1 };
+        let bytes = self.bytes.get(self.pos..self.pos + size).ok_or(TGAError::IncorrectFileLayout)?;
+        self.pos += size;
+        Ok(bytes.iter().fold(0, |v, &b| v << 8 | b as usize))
+    }
+}
+
+impl TGAImage {
+    pub fn from_pnm_file(filename: &str) -> TGAResult<Self> {
+        if let Ok(file) = File::open(filename) {
+            Self::read_pnm(&mut BufReader::new(file))
+        } else {
+            Err(TGAError::FileOpenError)
+        }
+    }
+
+    /// Read a plain or binary PGM or PPM (P2, P3, P5 and P6) or a PAM (P7)
+    /// image with 1 to 4 channels. Graymaps become `GRAYSCALE` images,
+    /// pixmaps `RGB` ones and PAM images with alpha `RGBA` ones. Samples
+    /// are scaled from the maximum value to 8 bits and the rows reordered
+    /// to the bottom left origin.
+    pub fn read_pnm<R: Read>(src: &mut R) -> TGAResult<Self> {
+        Self::read_pnm_with(src, TGAReadOptions::default())
+    }
+
+    pub fn read_pnm_with<R: Read>(src: &mut R, options: TGAReadOptions) -> TGAResult<Self> {
+        let mut bytes = vec![];
+        src.read_to_end(&mut bytes)?;
+        let mut parser = Parser { bytes: &bytes, pos: 0 };
+
+        let magic = parser.token().map_err(|_| TGAError::BadHeader)?;
+        let (width, height, channels, maxval) = match magic {
+            "P2" | "P3" | "P5" | "P6" => {
+                let channels = if magic == "P2" || magic == "P5" { 1 } else { 3 };
+                (parser.number()?, parser.number()?, channels, parser.number()?)
+            },
+            "P7" => {
+                let (mut width, mut height, mut depth, mut maxval) = (0, 0, 0, 0);
+                loop {
+                    let key = parser.token()?;
+                    let mut value = || parser.number();
+                    match key {
+                        "WIDTH" => width = value()?,
+                        "HEIGHT" => height = value()?,
+                        "DEPTH" => depth = value()?,
+                        "MAXVAL" => maxval = value()?,
+                        "ENDHDR" => break,
+                        // the depth tells the layout
+                        "TUPLTYPE" => parser.skip_line(),
+                        _ => return Err(TGAError::BadHeader),
+                    }
+                }
+                (width, height, depth, maxval)
+            },
+            _ => return Err(TGAError::BadHeader),
+        };
+        let bytespp = match channels {
+            1 => tga_format::GRAYSCALE,
+            3 => tga_format::RGB,
+            _ => tga_format::RGBA,
+        };
+        if width == 0 || height == 0 || !(1..=4).contains(&channels) || !(1..=65535).contains(&maxval) ||
+           options.data_size(width, height, bytespp as usize).is_none() {
+            return Err(TGAError::BadHeader)
+        }
+        let binary = magic != "P2" && magic != "P3";
+        if binary {
+            parser.end_header()?;
+        }
+        // check the samples can be there before allocating the image, plain
+        // samples take at least one byte each
+        let sample_size = if binary && maxval > 255 { 2 } else { 1 };
+        if width * height * channels * sample_size > bytes.len() - parser.pos {
+            return Err(TGAError::IncorrectFileLayout)
+        }
+
+        let mut image = TGAImage::with_size(width as i32, height as i32, bytespp);
+        image.origin = TGAOrigin::TopLeft;
+        let mut samples = [0u8; 4];
+        for dst in image.data.chunks_exact_mut(bytespp as usize) {
+            for sample in samples.iter_mut().take(channels) {
+                let v = if binary { parser.binary_sample(maxval)? } else { parser.number()? };
+                if v > maxval {
+                    return Err(TGAError::IncorrectFileLayout)
+                }
+                *sample = ((v * 255 + maxval / 2) / maxval) as u8;
+            }
+            match channels {
+                1 => dst[0] = samples[0],
+                2 => dst.copy_from_slice(&[samples[0], samples[0], samples[0], samples[1]]),
+                3 => dst.copy_from_slice(&[samples[2], samples[1], samples[0]]),
+                _ => dst.copy_from_slice(&[samples[2], samples[1], samples[0], samples[3]]),
+            }
+        }
+        image.set_origin(TGAOrigin::BottomLeft)?;
+        Ok(image)
+    }
+
+    pub fn write_pnm_file(&self, filename: &str, format: NetpbmFormat) -> TGAResult<()> {
+        if let Ok(file) = File::create(filename) {
+            let mut buffered_file = BufWriter::new(file);
+            self.write_pnm(&mut buffered_file, format)?;
+            buffered_file.flush()?;
+            Ok(())
+        } else {
+            Err(TGAError::FileOpenError)
+        }
+    }
+
+    /// Write the image with 8 bit samples. Grayscale images are written as
+    /// PGM and color ones as PPM, which drops the alpha of RGBA images,
+    /// unless `format` is [`NetpbmFormat::Pam`].
+    pub fn write_pnm<W: Write>(&self, dst: &mut W, format: NetpbmFormat) -> TGAResult<()> {
+        if self.data.is_empty() {
+            return Err(TGAError::EmptyImage)
+        }
+        let gray = self.bytespp == tga_format::GRAYSCALE;
+        let channels = match format {
+            NetpbmFormat::Pam => self.bytespp as usize,
+            _ if gray => 1,
+            _ => 3,
+        };
+        match format {
+            NetpbmFormat::Plain => write!(dst, "{}\n{} {}\n255\n", if gray { "P2" } else { "P3" }, self.width, self.height)?,
+            NetpbmFormat::Binary => write!(dst, "{}\n{} {}\n255\n", if gray { "P5" } else { "P6" }, self.width, self.height)?,
+            NetpbmFormat::Pam => {
+                let tuple_type = match self.bytespp {
+                    tga_format::GRAYSCALE => "GRAYSCALE",
+                    tga_format::RGB => "RGB",
+                    _ => "RGB_ALPHA",
+                };
+                write!(dst, "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL 255\nTUPLTYPE {}\nENDHDR\n",
+                       self.width, self.height, channels, tuple_type)?;
+            },
+        }
+
+        // rows go from the top in Netpbm files
+        let mut image = self.clone();
+        image.set_origin(TGAOrigin::TopLeft)?;
+        let mut samples = Vec::with_capacity(image.data.len());
+        for pixel in image.data.chunks_exact(self.bytespp as usize) {
+            match channels {
+                1 => samples.push(pixel[0]),
+                3 => samples.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]),
+                _ => samples.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]),
+            }
+        }
+
+        if format == NetpbmFormat::Plain {
+            // lines of plain files should not exceed 70 characters
+            for line in samples.chunks(17) {
+                let line: Vec<String> = line.iter().map(|v| v.to_string()).collect();
+                writeln!(dst, "{}", line.join(" "))?;
+            }
+        } else {
+            dst.write_all(&samples)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NetpbmFormat;
+    use crate::tgaimage::{tga_format, TGAColor, TGAError, TGAImage};
+
+    #[test]
+    fn read_plain_and_binary() {
+        // top row first, maximum value 15
+        let plain = b"P3\n# comment\n2 2 15\n15 0 0  0 15 0\n0 0 15  3 6 9\n";
+        let image = TGAImage::read_pnm(&mut plain.as_slice()).unwrap();
+        assert_eq!(image.bytespp(), tga_format::RGB);
+        assert_eq!(image.get(0, 1).unwrap(), TGAColor::from_rgb(255, 0, 0));
+        assert_eq!(image.get(1, 1).unwrap(), TGAColor::from_rgb(0, 255, 0));
+        assert_eq!(image.get(1, 0).unwrap(), TGAColor::from_rgb(51, 102, 153));
+
+        let mut binary = b"P5 3 1 65535\n".to_vec();
+        binary.extend_from_slice(&[0x00, 0x00, 0x80, 0x00, 0xFF, 0xFF]);
+        let image = TGAImage::read_pnm(&mut binary.as_slice()).unwrap();
+        assert_eq!(image.bytespp(), tga_format::GRAYSCALE);
+        let row = [0, 1, 2].map(|x| image.get(x, 0).unwrap().b());
+        assert_eq!(row, [0, 128, 255]);
+
+        let truncated = b"P6 2 2 255\n\x01\x02\x03";
+        assert!(TGAImage::read_pnm(&mut truncated.as_slice()).is_err());
+    }
+
+    #[test]
+    fn reject_oversized_headers() {
+        let oversized = b"P5 65536 65536 255\n\0";
+        assert!(matches!(TGAImage::read_pnm(&mut oversized.as_slice()), Err(TGAError::BadHeader)));
+        let overflowing = b"P3 18446744073709551615 2 255\n0 0 0";
+        assert!(matches!(TGAImage::read_pnm(&mut overflowing.as_slice()), Err(TGAError::BadHeader)));
+
+        // fits in memory but the file is far too short
+        let truncated = b"P6 4000 4000 65535\n\0\0\0\0\0\0";
+        assert!(matches!(TGAImage::read_pnm(&mut truncated.as_slice()), Err(TGAError::IncorrectFileLayout)));
+    }
+
+    #[test]
+    fn read_pam_with_alpha() {
+        let mut file = b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 2\nMAXVAL 255\nTUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n".to_vec();
+        file.extend_from_slice(&[10, 20, 200, 255]);
+        let image = TGAImage::read_pnm(&mut file.as_slice()).unwrap();
+        assert_eq!(image.bytespp(), tga_format::RGBA);
+        assert_eq!(image.get(0, 0).unwrap(), TGAColor::from_rgba(10, 10, 10, 20));
+        assert_eq!(image.get(1, 0).unwrap(), TGAColor::from_rgba(200, 200, 200, 255));
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        for bytespp in [tga_format::GRAYSCALE, tga_format::RGB, tga_format::RGBA] {
+            let mut image = TGAImage::with_size(13, 7, bytespp);
+            for y in 0..7 {
+                for x in 0..13 {
+                    let v = |k: i32| ((x * k + y * 29) % 256) as u8;
+                    image.set(x, y, TGAColor::from_rgba(v(7), v(3), v(11), v(5))).unwrap();
+                }
+            }
+            let read_back = |format| {
+                let mut file = vec![];
+                image.write_pnm(&mut file, format).unwrap();
+                TGAImage::read_pnm(&mut file.as_slice()).unwrap()
+            };
+            assert_eq!(read_back(NetpbmFormat::Pam), image, "{}", bytespp);
+
+            // PGM and PPM have no alpha
+            let expected = if bytespp == tga_format::RGBA {
+                let mut opaque = TGAImage::with_size(13, 7, tga_format::RGB);
+                for y in 0..7 {
+                    for x in 0..13 {
+                        let c = image.get(x, y).unwrap();
+                        opaque.set(x, y, TGAColor::from_rgb(c.r(), c.g(), c.b())).unwrap();
+                    }
+                }
+                opaque
+            } else {
+                image.clone()
+            };
+            assert_eq!(read_back(NetpbmFormat::Plain), expected, "{}", bytespp);
+            assert_eq!(read_back(NetpbmFormat::Binary), expected, "{}", bytespp);
+        }
+    }
+
+    #[test]
+    fn plain_lines_are_short() {
+        let mut image = TGAImage::with_size(40, 2, tga_format::RGB);
+        image.data.fill(255);
+        let mut file = vec![];
+        image.write_pnm(&mut file, NetpbmFormat::Plain).unwrap();
+        assert!(String::from_utf8(file).unwrap().lines().all(|line| line.len() <= 70));
+    }
+}
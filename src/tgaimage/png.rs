@@ -0,0 +1,426 @@
+//! PNG reading and writing of [`TGAImage`].
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use super::{tga_format, zlib, TGAError, TGAImage, TGAOrigin, TGAReadOptions, TGAResult};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+const COLOR_GRAY: u8 = 0;
+const COLOR_RGB: u8 = 2;
+const COLOR_PALETTE: u8 = 3;
+const COLOR_GRAY_ALPHA: u8 = 4;
+const COLOR_RGBA: u8 = 6;
+
+/// Image layout given by the IHDR chunk.
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+}
+
+impl Header {
+    fn parse(data: &[u8], options: &TGAReadOptions) -> TGAResult<Header> {
+        if data.len() != 13 {
+            return Err(TGAError::BadHeader)
+        }
+        let header = Header {
+            width: u32::from_be_bytes(data[0..4].try_into().unwrap()) as usize,
+            height: u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize,
+            bit_depth: data[8],
+            color_type: data[9],
+        };
+        let valid_depth = match header.color_type {
+            COLOR_GRAY => matches!(header.bit_depth, 1 | 2 | 4 | 8 | 16),
+            COLOR_PALETTE => matches!(header.bit_depth, 1 | 2 | 4 | 8),
+            COLOR_RGB | COLOR_GRAY_ALPHA | COLOR_RGBA => matches!(header.bit_depth, 8 | 16),
+            _ => false,
+        };
+        // deflate compression, adaptive filtering, no interlacing, room for
+        // the RGBA image any color type may become
+        let (compression, filter, interlace) = (data[10], data[11], data[12]);
+        if !valid_depth || compression != 0 || filter != 0 || interlace != 0 ||
+           header.width == 0 || header.height == 0 || options.data_size(header.width, header.height, 4).is_none() {
+            return Err(TGAError::BadHeader)
+        }
+        Ok(header)
+    }
+
+    fn channels(&self) -> usize {
+        match self.color_type {
+            COLOR_RGB => 3,
+            COLOR_GRAY_ALPHA => 2,
+            COLOR_RGBA => 4,
+            _ => 1,
+        }
+    }
+
+    /// Bytes of a row without the filter type byte.
+    fn stride(&self) -> usize {
+        (self.width * self.channels() * self.bit_depth as usize).div_ceil(8)
+    }
+
+    /// Distance in bytes to the same component of the previous pixel used by
+    /// the filters, 1 for pixels smaller than a byte.
+    fn filter_bpp(&self) -> usize {
+        (self.channels() * self.bit_depth as usize / 8).max(1)
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Prediction of a byte by the filter type from the byte to the left `a`,
+/// above `b` and above left `c`.
+fn predict(filter: u8, a: u8, b: u8, c: u8) -> u8 {
+    match filter {
+        1 => a,
+        2 => b,
+        3 => ((a as u16 + b as u16) / 2) as u8,
+        4 => paeth(a, b, c),
+        _ => 0,
+    }
+}
+
+/// Reverse the filters of the rows in place, returns the unfiltered rows
+/// without the filter type bytes.
+fn unfilter(data: &mut [u8], header: &Header) -> TGAResult<Vec<u8>> {
+    let (stride, bpp) = (header.stride(), header.filter_bpp());
+    if data.len() < (stride + 1) * header.height {
+        return Err(TGAError::IncorrectFileLayout)
+    }
+    let mut res = vec![0u8; stride * header.height];
+    for y in 0..header.height {
+        let filter = data[y * (stride + 1)];
+        if filter > 4 {
+            return Err(TGAError::IncorrectFileLayout)
+        }
+        let src = &data[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (done, rest) = res.split_at_mut(y * stride);
+        let prev = done.get(done.len().saturating_sub(stride)..).filter(|_| y > 0);
+        let row = &mut rest[..stride];
+        for x in 0..stride {
+            let a = if x >= bpp { row[x - bpp] } else { 0 };
+            let b = prev.map_or(0, |prev| prev[x]);
+            let c = if x >= bpp { prev.map_or(0, |prev| prev[x - bpp]) } else { 0 };
+            row[x] = src[x].wrapping_add(predict(filter, a, b, c));
+        }
+    }
+    Ok(res)
+}
+
+/// `index`th sample of the row, scaled to 8 bits unless it is a palette
+/// index.
+fn sample(row: &[u8], index: usize, header: &Header) -> u8 {
+    match header.bit_depth {
+        8 => row[index],
+        16 => row[2 * index],
+        depth => {
+            let bit = index * depth as usize;
+            let shift = 8 - depth as usize - bit % 8;
+            let mask = (1u16 << depth) - 1;
+            let v = (row[bit / 8] >> shift) as u16 & mask;
+            if header.color_type == COLOR_PALETTE { v as u8 } else { (v * 255 / mask) as u8 }
+        },
+    }
+}
+
+impl TGAImage {
+    pub fn from_png_file(filename: &str) -> TGAResult<Self> {
+        if let Ok(file) = File::open(filename) {
+            Self::read_png(&mut BufReader::new(file))
+        } else {
+            Err(TGAError::FileOpenError)
+        }
+    }
+
+    /// Read a non-interlaced PNG image of any color type and bit depth.
+    /// Grayscale images become `GRAYSCALE` ones, the ones with alpha or a
+    /// palette transparency become `RGBA` and the others `RGB`. 16 bit
+    /// samples are reduced to 8 bits and the rows are reordered to the
+    /// bottom left origin.
+    pub fn read_png<R: Read>(src: &mut R) -> TGAResult<Self> {
+        Self::read_png_with(src, TGAReadOptions::default())
+    }
+
+    pub fn read_png_with<R: Read>(src: &mut R, options: TGAReadOptions) -> TGAResult<Self> {
+        let mut bytes = vec![];
+        src.read_to_end(&mut bytes)?;
+        if bytes.len() < SIGNATURE.len() || bytes[..SIGNATURE.len()] != SIGNATURE {
+            return Err(TGAError::BadHeader)
+        }
+
+        let mut header = None;
+        let mut palette: &[u8] = &[];
+        let mut transparency: &[u8] = &[];
+        let mut compressed = vec![];
+        let mut pos = SIGNATURE.len();
+        loop {
+            let length_bytes = bytes.get(pos..pos + 4).ok_or(TGAError::IncorrectFileLayout)?;
+            let length = u32::from_be_bytes(length_bytes.try_into().unwrap()) as usize;
+            let chunk = bytes.get(pos + 4..pos + 8 + length).ok_or(TGAError::IncorrectFileLayout)?;
+            let crc = bytes.get(pos + 8 + length..pos + 12 + length).ok_or(TGAError::IncorrectFileLayout)?;
+            if zlib::crc32(chunk) != u32::from_be_bytes(crc.try_into().unwrap()) {
+                return Err(TGAError::IncorrectFileLayout)
+            }
+            pos += 12 + length;
+
+            let (kind, data) = chunk.split_at(4);
+            match kind {
+                b"IHDR" => header = Some(Header::parse(data, &options)?),
+                _ if header.is_none() => return Err(TGAError::BadHeader),
+                b"PLTE" => palette = data,
+                b"tRNS" => transparency = data,
+                b"IDAT" => compressed.extend_from_slice(data),
+                b"IEND" => break,
+                // ancillary chunks are safe to skip
+                _ if kind[0].is_ascii_lowercase() => {},
+                _ => return Err(TGAError::BadHeader),
+            }
+        }
+        let header = header.ok_or(TGAError::BadHeader)?;
+
+        // filter type byte and the samples of every row
+        let rows_size = (header.stride() + 1) * header.height;
+        let rows = unfilter(&mut zlib::decompress(&compressed, rows_size)?, &header)?;
+        let bytespp = match header.color_type {
+            COLOR_GRAY => tga_format::GRAYSCALE,
+            COLOR_RGB => tga_format::RGB,
+            COLOR_PALETTE if transparency.is_empty() => tga_format::RGB,
+            _ => tga_format::RGBA,
+        };
+        let mut image = TGAImage::with_size(header.width as i32, header.height as i32, bytespp);
+        image.origin = TGAOrigin::TopLeft;
+        let bpp = bytespp as usize;
+        for (row, dst_row) in rows.chunks_exact(header.stride()).zip(image.data.chunks_exact_mut(header.width * bpp)) {
+            for (x, dst) in dst_row.chunks_exact_mut(bpp).enumerate() {
+                let s = |i: usize| sample(row, x * header.channels() + i, &header);
+                match header.color_type {
+                    COLOR_GRAY => dst[0] = s(0),
+                    COLOR_RGB => dst.copy_from_slice(&[s(2), s(1), s(0)]),
+                    COLOR_PALETTE => {
+                        let index = s(0) as usize;
+                        let entry = palette.get(3 * index..3 * index + 3).ok_or(TGAError::IncorrectFileLayout)?;
+                        dst[..3].copy_from_slice(&[entry[2], entry[1], entry[0]]);
+                        if bpp == 4 {
+                            dst[3] = transparency.get(index).copied().unwrap_or(255);
+                        }
+                    },
+                    COLOR_GRAY_ALPHA => dst.copy_from_slice(&[s(0), s(0), s(0), s(1)]),
+                    _ => dst.copy_from_slice(&[s(2), s(1), s(0), s(3)]),
+                }
+            }
+        }
+        image.set_origin(TGAOrigin::BottomLeft)?;
+        Ok(image)
+    }
+
+    pub fn write_png_file(&self, filename: &str) -> TGAResult<()> {
+        if let Ok(file) = File::create(filename) {
+            let mut buffered_file = BufWriter::new(file);
+            self.write_png(&mut buffered_file)?;
+            buffered_file.flush()?;
+            Ok(())
+        } else {
+            Err(TGAError::FileOpenError)
+        }
+    }
+
+    /// Write the image as an 8 bit grayscale, RGB or RGBA PNG.
+    pub fn write_png<W: Write>(&self, dst: &mut W) -> TGAResult<()> {
+        if self.data.is_empty() {
+            return Err(TGAError::EmptyImage)
+        }
+        let color_type = match self.bytespp {
+            tga_format::GRAYSCALE => COLOR_GRAY,
+            tga_format::RGB => COLOR_RGB,
+            _ => COLOR_RGBA,
+        };
+        let header = Header { width: self.width as usize, height: self.height as usize, bit_depth: 8, color_type };
+
+        // rows go from the top in PNG files
+        let mut image = self.clone();
+        image.set_origin(TGAOrigin::TopLeft)?;
+        let bpp = self.bytespp as usize;
+        let stride = header.stride();
+        let mut rows = vec![0u8; stride * header.height];
+        for (src, dst) in image.data.chunks_exact(bpp).zip(rows.chunks_exact_mut(bpp)) {
+            dst.copy_from_slice(src);
+            if bpp >= 3 {
+                dst.swap(0, 2);
+            }
+        }
+
+        // the filter giving the smallest sum of absolute differences is
+        // usually the one that compresses best
+        let mut filtered = Vec::with_capacity((stride + 1) * header.height);
+        let mut candidate = vec![0u8; stride];
+        let mut best = vec![0u8; stride];
+        for y in 0..header.height {
+            let row = &rows[y * stride..(y + 1) * stride];
+            let prev = if y > 0 { Some(&rows[(y - 1) * stride..y * stride]) } else { None };
+            let mut best_filter = (0, u64::MAX);
+            for filter in 0..=4u8 {
+                for x in 0..stride {
+                    let a = if x >= bpp { row[x - bpp] } else { 0 };
+                    let b = prev.map_or(0, |prev| prev[x]);
+                    let c = if x >= bpp { prev.map_or(0, |prev| prev[x - bpp]) } else { 0 };
+                    candidate[x] = row[x].wrapping_sub(predict(filter, a, b, c));
+                }
+                let cost = candidate.iter().map(|&v| (v as i8).unsigned_abs() as u64).sum();
+                if cost < best_filter.1 {
+                    best_filter = (filter, cost);
+                    best.copy_from_slice(&candidate);
+                }
+            }
+            filtered.push(best_filter.0);
+            filtered.extend_from_slice(&best);
+        }
+
+        let mut ihdr = vec![];
+        ihdr.extend_from_slice(&(header.width as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(header.height as u32).to_be_bytes());
+        ihdr.extend_from_slice(&[8, color_type, 0, 0, 0]);
+
+        dst.write_all(&SIGNATURE)?;
+        write_chunk(dst, b"IHDR", &ihdr)?;
+        write_chunk(dst, b"IDAT", &zlib::compress(&filtered))?;
+        write_chunk(dst, b"IEND", &[])?;
+        Ok(())
+    }
+}
+
+fn write_chunk<W: Write>(dst: &mut W, kind: &[u8; 4], data: &[u8]) -> TGAResult<()> {
+    dst.write_all(&(data.len() as u32).to_be_bytes())?;
+    dst.write_all(kind)?;
+    dst.write_all(data)?;
+    let crc = zlib::crc32_update(zlib::crc32(kind), data);
+    dst.write_all(&crc.to_be_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tgaimage::{tga_format, zlib, TGAColor, TGAError, TGAImage, TGAOrigin, TGAReadOptions};
+
+    #[test]
+    fn read_filtered_rgb() {
+        // 3x3, rows filtered with Sub, Paeth and Average
+        let file = [
+            0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52,
+            0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x03, 0x08, 0x02, 0x00, 0x00, 0x00, 0xD9, 0x4A, 0x22,
+            0xE8, 0x00, 0x00, 0x00, 0x23, 0x49, 0x44, 0x41, 0x54, 0x78, 0xDA, 0x63, 0xFC, 0xCF, 0xC0, 0xC0,
+            0x08, 0xC6, 0x2C, 0xDC, 0x22, 0x72, 0x1A, 0xC6, 0x72, 0x72, 0x01, 0xD1, 0xCC, 0xBF, 0xBE, 0x7E,
+            0x78, 0xFB, 0xE2, 0xF1, 0xDC, 0x19, 0x93, 0x01, 0x88, 0x54, 0x0B, 0xE6, 0x38, 0x7A, 0x55, 0x1D,
+            0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+        ];
+        let image = TGAImage::read_png(&mut file.as_slice()).unwrap();
+        assert_eq!(image.bytespp(), tga_format::RGB);
+        assert_eq!(image.origin(), TGAOrigin::BottomLeft);
+        // the top row comes first in the file
+        assert_eq!(image.get(0, 2).unwrap(), TGAColor::from_rgb(255, 0, 0));
+        assert_eq!(image.get(2, 2).unwrap(), TGAColor::from_rgb(0, 0, 255));
+        assert_eq!(image.get(1, 1).unwrap(), TGAColor::from_rgb(40, 50, 60));
+        assert_eq!(image.get(1, 0).unwrap(), TGAColor::from_rgb(128, 128, 128));
+    }
+
+    #[test]
+    fn read_palette_with_transparency() {
+        // 3x2 with 2 bit indices, the first two entries have alpha
+        let file = [
+            0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52,
+            0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x02, 0x02, 0x03, 0x00, 0x00, 0x00, 0xE0, 0x1A, 0x8E,
+            0x89, 0x00, 0x00, 0x00, 0x0C, 0x50, 0x4C, 0x54, 0x45, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00,
+            0xFF, 0x00, 0x00, 0x00, 0xFF, 0x9B, 0xC0, 0x13, 0xDC, 0x00, 0x00, 0x00, 0x02, 0x74, 0x52, 0x4E,
+            0x53, 0x00, 0x80, 0x9B, 0x2B, 0x4E, 0x18, 0x00, 0x00, 0x00, 0x0C, 0x49, 0x44, 0x41, 0x54, 0x78,
+            0xDA, 0x63, 0xC8, 0x61, 0x5A, 0x03, 0x00, 0x01, 0xF8, 0x01, 0x1B, 0x31, 0xA0, 0x66, 0xCB, 0x00,
+            0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+        ];
+        let image = TGAImage::read_png(&mut file.as_slice()).unwrap();
+        assert_eq!(image.bytespp(), tga_format::RGBA);
+        let top = [0, 1, 2].map(|x| image.get(x, 1).unwrap());
+        assert_eq!(top, [TGAColor::from_rgba(255, 0, 0, 128),
+                         TGAColor::from_rgba(0, 255, 0, 255),
+                         TGAColor::from_rgba(0, 0, 255, 255)]);
+        assert_eq!(image.get(0, 0).unwrap(), TGAColor::from_rgba(0, 0, 0, 0));
+    }
+
+    #[test]
+    fn read_16_bit_grayscale() {
+        let file = [
+            0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52,
+            0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x10, 0x00, 0x00, 0x00, 0x00, 0x81, 0xD9, 0xFC,
+            0x15, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x44, 0x41, 0x54, 0x78, 0xDA, 0x63, 0x10, 0x32, 0x59, 0x7D,
+            0x16, 0x00, 0x03, 0x0C, 0x01, 0xBF, 0xB1, 0xE7, 0xD4, 0x4D, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45,
+            0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+        ];
+        let image = TGAImage::read_png(&mut file.as_slice()).unwrap();
+        assert_eq!(image.bytespp(), tga_format::GRAYSCALE);
+        assert_eq!(image.get(0, 0).unwrap().b(), 0x12);
+        assert_eq!(image.get(1, 0).unwrap().b(), 0xAB);
+
+        let mut corrupt = file;
+        corrupt[44] ^= 1;
+        assert!(TGAImage::read_png(&mut corrupt.as_slice()).is_err());
+    }
+
+    #[test]
+    fn reject_oversized_header() {
+        let mut ihdr = b"IHDR".to_vec();
+        ihdr.extend_from_slice(&65536u32.to_be_bytes());
+        ihdr.extend_from_slice(&65536u32.to_be_bytes());
+        ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+        let mut file = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n', 0, 0, 0, 13];
+        file.extend_from_slice(&ihdr);
+        file.extend_from_slice(&zlib::crc32(&ihdr).to_be_bytes());
+        assert!(matches!(TGAImage::read_png(&mut file.as_slice()), Err(TGAError::BadHeader)));
+    }
+
+    #[test]
+    fn limit_image_data() {
+        let mut file = vec![];
+        TGAImage::with_size(40, 40, tga_format::RGB).write_png(&mut file).unwrap();
+        let options = TGAReadOptions { max_pixels: 1000 };
+        assert!(matches!(TGAImage::read_png_with(&mut file.as_slice(), options), Err(TGAError::BadHeader)));
+
+        // more pixel data than the header says, IHDR is the first chunk
+        file[8 + 8 + 7] = 20;
+        let crc = zlib::crc32(&file[12..12 + 4 + 13]);
+        file[12 + 4 + 13..12 + 4 + 13 + 4].copy_from_slice(&crc.to_be_bytes());
+        assert!(matches!(TGAImage::read_png(&mut file.as_slice()), Err(TGAError::IncorrectFileLayout)));
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        for bytespp in [tga_format::GRAYSCALE, tga_format::RGB, tga_format::RGBA] {
+            let mut image = TGAImage::with_size(37, 21, bytespp);
+            for y in 0..21 {
+                for x in 0..37 {
+                    let v = |k: i32| ((x * k + y * y * 3) % 256) as u8;
+                    image.set(x, y, TGAColor::from_rgba(v(7), v(3), v(11), v(5))).unwrap();
+                }
+            }
+            let mut file = vec![];
+            image.write_png(&mut file).unwrap();
+            assert_eq!(TGAImage::read_png(&mut file.as_slice()).unwrap(), image, "{}", bytespp);
+
+            // same picture stored from the top
+            let mut top_left = image.clone();
+            top_left.set_origin(TGAOrigin::TopLeft).unwrap();
+            let mut top_left_file = vec![];
+            top_left.write_png(&mut top_left_file).unwrap();
+            assert_eq!(top_left_file, file);
+        }
+    }
+}
@@ -0,0 +1,565 @@
+//! zlib streams (RFC 1950) of deflate data (RFC 1951) and the checksums of
+//! the PNG format.
+//!
+//! The compressor emits stored and fixed Huffman blocks, whichever is
+//! smaller, the decompressor reads all the block types.
+
+use super::{TGAError, TGAResult};
+
+/// CRC-32 (ISO 3309) of the data, as in the PNG chunks.
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// CRC-32 of the data following the data the `crc` was computed for.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    static TABLE: std::sync::OnceLock<[u32; 256]> = std::sync::OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut table = [0u32; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 };
+            }
+            *entry = c;
+        }
+        table
+    });
+    let mut c = !crc;
+    for &byte in data {
+        c = table[((c ^ byte as u32) & 0xFF) as usize] ^ (c >> 8);
+    }
+    !c
+}
+
+/// Adler-32 checksum of the data, as at the end of zlib streams.
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // sums of 5552 bytes can't overflow before taking the modulo
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+/// Order of the code length code lengths in dynamic block headers.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+const END_OF_BLOCK: u16 = 256;
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Longest hash chain followed when looking for a match.
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+/// Most bytes a stored block can hold.
+const MAX_STORED: usize = 65535;
+
+/// Bits written least significant first, Huffman codes most significant
+/// first.
+struct BitWriter {
+    out: Vec<u8>,
+    buf: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter { out: vec![], buf: 0, count: 0 }
+    }
+
+    fn write(&mut self, value: u32, bits: u32) {
+        self.buf |= (value as u64) << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.out.push(self.buf as u8);
+            self.buf >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn write_code(&mut self, code: u32, bits: u32) {
+        self.write(code.reverse_bits() >> (32 - bits), bits);
+    }
+
+    /// Pad to the byte boundary with zero bits.
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.write(0, 8 - self.count);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+/// Fixed Huffman code and its length of a literal/length symbol.
+fn fixed_literal_code(symbol: u16) -> (u32, u32) {
+    let symbol = symbol as u32;
+    match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xC0 + symbol - 280, 8),
+    }
+}
+
+/// Index of the largest base not greater than `value`.
+fn base_index(bases: &[u16], value: u16) -> usize {
+    bases.partition_point(|&base| base <= value) - 1
+}
+
+impl Token {
+    /// Number of bits of the token in a fixed Huffman block.
+    fn fixed_bits(&self) -> u32 {
+        match *self {
+            Token::Literal(byte) => fixed_literal_code(byte as u16).1,
+            Token::Match { length, distance } => {
+                let l = base_index(&LENGTH_BASE, length);
+                let d = base_index(&DIST_BASE, distance);
+                fixed_literal_code(257 + l as u16).1 + LENGTH_EXTRA[l] as u32 + 5 + DIST_EXTRA[d] as u32
+            },
+        }
+    }
+
+    /// Number of input bytes the token stands for.
+    fn len(&self) -> usize {
+        match *self {
+            Token::Literal(_) => 1,
+            Token::Match { length, .. } => length as usize,
+        }
+    }
+
+    fn write_fixed(&self, out: &mut BitWriter) {
+        match *self {
+            Token::Literal(byte) => {
+                let (code, bits) = fixed_literal_code(byte as u16);
+                out.write_code(code, bits);
+            },
+            Token::Match { length, distance } => {
+                let l = base_index(&LENGTH_BASE, length);
+                let (code, bits) = fixed_literal_code(257 + l as u16);
+                out.write_code(code, bits);
+                out.write((length - LENGTH_BASE[l]) as u32, LENGTH_EXTRA[l] as u32);
+                let d = base_index(&DIST_BASE, distance);
+                out.write_code(d as u32, 5);
+                out.write((distance - DIST_BASE[d]) as u32, DIST_EXTRA[d] as u32);
+            },
+        }
+    }
+}
+
+/// LZ77 matches of the data found with hash chains, greedily taking the
+/// longest match at every position.
+fn find_matches(data: &[u8]) -> Vec<Token> {
+    let hash = |i: usize| {
+        let v = (data[i] as u32) << 16 | (data[i + 1] as u32) << 8 | data[i + 2] as u32;
+        (v.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    };
+    // most recent position of every hash and the previous position with
+    // the same hash of every position in the window
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - i);
+            let mut candidate = head[hash(i)];
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || i - candidate > WINDOW_SIZE {
+                    break;
+                }
+                let len = data[candidate..].iter().zip(&data[i..i + max_len]).take_while(|(a, b)| a == b).count();
+                if len > best.0 {
+                    best = (len, i - candidate);
+                    if len == max_len {
+                        break;
+                    }
+                }
+                let next = prev[candidate % WINDOW_SIZE];
+                // the slot may have been reused by a newer position
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+            }
+        }
+
+        let token = if best.0 >= MIN_MATCH {
+            Token::Match { length: best.0 as u16, distance: best.1 as u16 }
+        } else {
+            Token::Literal(data[i])
+        };
+        for j in (i..i + token.len()).take_while(|j| j + MIN_MATCH <= data.len()) {
+            let h = hash(j);
+            prev[j % WINDOW_SIZE] = head[h];
+            head[h] = j;
+        }
+        i += token.len();
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// Compress the data into a zlib stream.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = BitWriter::new();
+    // deflate with a 32K window, default compression level
+    out.write(0x78, 8);
+    out.write(0x9C, 8);
+
+    let tokens = find_matches(data);
+    let mut start = 0;
+    let mut pos = 0;
+    loop {
+        // blocks small enough to be stored if they don't compress
+        let first = start;
+        let mut len = 0;
+        let mut bits = 7;
+        while start < tokens.len() && len + tokens[start].len() <= MAX_STORED {
+            len += tokens[start].len();
+            bits += tokens[start].fixed_bits();
+            start += 1;
+        }
+        let last = start == tokens.len();
+        out.write(last as u32, 1);
+
+        let stored_bits = 7 + 32 + 8 * len as u32;
+        if bits <= stored_bits {
+            out.write(1, 2);
+            for token in &tokens[first..start] {
+                token.write_fixed(&mut out);
+            }
+            let (code, bits) = fixed_literal_code(END_OF_BLOCK);
+            out.write_code(code, bits);
+        } else {
+            out.write(0, 2);
+            out.align();
+            out.write(len as u32, 16);
+            out.write(!len as u32 & 0xFFFF, 16);
+            out.out.extend_from_slice(&data[pos..pos + len]);
+        }
+        pos += len;
+        if last {
+            break;
+        }
+    }
+
+    out.align();
+    out.out.extend_from_slice(&adler32(data).to_be_bytes());
+    out.out
+}
+
+/// Bits read least significant first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, n: u32) -> TGAResult<u32> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or(TGAError::IncorrectFileLayout)?;
+            self.buf |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let res = self.buf & ((1u64 << n) - 1) as u32;
+        self.buf = if n == 32 { 0 } else { self.buf >> n };
+        self.count -= n;
+        Ok(res)
+    }
+
+    /// Drop the bits up to the byte boundary.
+    fn align(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+}
+
+/// Canonical Huffman code given by the code lengths of its symbols.
+struct Huffman {
+    /// Number of codes of every length.
+    counts: [u16; 16],
+    /// Symbols ordered by their codes.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn fixed_literals() -> Huffman {
+        let mut lengths = [8u8; 288];
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        Huffman::new(&lengths)
+    }
+
+    fn fixed_distances() -> Huffman {
+        Huffman::new(&[5u8; 30])
+    }
+
+    fn decode(&self, input: &mut BitReader) -> TGAResult<u16> {
+        // codes of every length are consecutive, starting at `first`
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= input.bits(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize])
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(TGAError::IncorrectFileLayout)
+    }
+}
+
+/// Literal/length and distance codes of a dynamic block.
+fn read_dynamic_codes(input: &mut BitReader) -> TGAResult<(Huffman, Huffman)> {
+    let literals = input.bits(5)? as usize + 257;
+    let distances = input.bits(5)? as usize + 1;
+    let code_lengths = input.bits(4)? as usize + 4;
+    if literals > 286 || distances > 30 {
+        return Err(TGAError::IncorrectFileLayout)
+    }
+
+    let mut lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[i] = input.bits(3)? as u8;
+    }
+    let length_code = Huffman::new(&lengths);
+
+    let mut lengths = vec![0u8; literals + distances];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = length_code.decode(input)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let prev = *lengths[..i].last().ok_or(TGAError::IncorrectFileLayout)?;
+                (prev, 3 + input.bits(2)? as usize)
+            },
+            17 => (0, 3 + input.bits(3)? as usize),
+            _ => (0, 11 + input.bits(7)? as usize),
+        };
+        if i + repeat > lengths.len() {
+            return Err(TGAError::IncorrectFileLayout)
+        }
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+    if lengths[END_OF_BLOCK as usize] == 0 {
+        return Err(TGAError::IncorrectFileLayout)
+    }
+    Ok((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+}
+
+fn inflate_block(input: &mut BitReader,
+                 literals: &Huffman,
+                 distances: &Huffman,
+                 out: &mut Vec<u8>,
+                 max_len: usize) -> TGAResult<()> {
+    loop {
+        // a match is at most 258 bytes, checking before each symbol keeps
+        // the output close to the limit
+        if out.len() > max_len {
+            return Err(TGAError::IncorrectFileLayout)
+        }
+        let symbol = literals.decode(input)?;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            END_OF_BLOCK => return Ok(()),
+            _ => {
+                let l = (symbol - 257) as usize;
+                if l >= LENGTH_BASE.len() {
+                    return Err(TGAError::IncorrectFileLayout)
+                }
+                let length = LENGTH_BASE[l] as usize + input.bits(LENGTH_EXTRA[l] as u32)? as usize;
+                let d = distances.decode(input)? as usize;
+                if d >= DIST_BASE.len() {
+                    return Err(TGAError::IncorrectFileLayout)
+                }
+                let distance = DIST_BASE[d] as usize + input.bits(DIST_EXTRA[d] as u32)? as usize;
+                if distance > out.len() {
+                    return Err(TGAError::IncorrectFileLayout)
+                }
+                // the match may overlap the bytes it produces
+                let from = out.len() - distance;
+                for k in 0..length {
+                    out.push(out[from + k]);
+                }
+            },
+        }
+    }
+}
+
+/// Decompress a zlib stream, checking its checksum. Streams of more than
+/// `max_len` bytes are rejected before they are decompressed in full.
+pub fn decompress(data: &[u8], max_len: usize) -> TGAResult<Vec<u8>> {
+    if data.len() < 6 {
+        return Err(TGAError::IncorrectFileLayout)
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    // deflate method, no preset dictionary
+    if cmf & 0x0F != 8 || !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) || flg & 0x20 != 0 {
+        return Err(TGAError::IncorrectFileLayout)
+    }
+
+    let mut input = BitReader { data, pos: 2, buf: 0, count: 0 };
+    let mut out = vec![];
+    loop {
+        let last = input.bits(1)? == 1;
+        match input.bits(2)? {
+            0 => {
+                input.align();
+                let len = input.bits(16)?;
+                if input.bits(16)? != !len & 0xFFFF {
+                    return Err(TGAError::IncorrectFileLayout)
+                }
+                let bytes = data.get(input.pos..input.pos + len as usize).ok_or(TGAError::IncorrectFileLayout)?;
+                if out.len() + bytes.len() > max_len {
+                    return Err(TGAError::IncorrectFileLayout)
+                }
+                out.extend_from_slice(bytes);
+                input.pos += len as usize;
+            },
+            1 => inflate_block(&mut input, &Huffman::fixed_literals(), &Huffman::fixed_distances(), &mut out, max_len)?,
+            2 => {
+                let (literals, distances) = read_dynamic_codes(&mut input)?;
+                inflate_block(&mut input, &literals, &distances, &mut out, max_len)?;
+            },
+            _ => return Err(TGAError::IncorrectFileLayout),
+        }
+        if last {
+            break;
+        }
+    }
+    if out.len() > max_len {
+        return Err(TGAError::IncorrectFileLayout)
+    }
+
+    input.align();
+    let checksum = data.get(input.pos..input.pos + 4).ok_or(TGAError::IncorrectFileLayout)?;
+    if u32::from_be_bytes(checksum.try_into().unwrap()) != adler32(&out) {
+        return Err(TGAError::IncorrectFileLayout)
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{adler32, compress, crc32, decompress};
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng = StdRng::seed_from_u64(23);
+        let noise: Vec<u8> = (0..100_000).map(|_| rng.gen()).collect();
+        let runs: Vec<u8> = (0..200_000).map(|i| (i / 1000 % 7) as u8).collect();
+        let text = b"the quick brown fox jumps over the lazy dog, ".repeat(50);
+        for data in [&[][..], &b"a"[..], &noise, &runs, &text] {
+            let compressed = compress(data);
+            assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+        }
+        // stored blocks for incompressible data, matches otherwise
+        assert!(compress(&noise).len() < noise.len() + 100);
+        assert!(compress(&runs).len() < runs.len() / 50);
+    }
+
+    #[test]
+    fn output_length_is_limited() {
+        let runs = vec![7u8; 1_000_000];
+        assert!(decompress(&compress(&runs), 1000).is_err());
+        assert!(decompress(&compress(&runs), runs.len() - 1).is_err());
+        // stored blocks
+        let mut rng = StdRng::seed_from_u64(29);
+        let noise: Vec<u8> = (0..1000).map(|_| rng.gen()).collect();
+        assert!(decompress(&compress(&noise), noise.len() - 1).is_err());
+    }
+
+    #[test]
+    fn decompress_dynamic_block() {
+        // compressed by zlib, which picked a dynamic Huffman block
+        let stream = [
+            0x78, 0x01, 0x75, 0xC9, 0xC5, 0x01, 0x80, 0x30, 0x10, 0x04, 0xC0, 0x56, 0xB6, 0xB5, 0xB8, 0x73,
+            0x71, 0xAB, 0x1E, 0x1A, 0x60, 0xBE, 0xD3, 0xAD, 0x42, 0x19, 0x4E, 0x04, 0xF0, 0x4A, 0xEB, 0x81,
+            0xA6, 0x0D, 0x3F, 0x52, 0x6E, 0xA0, 0xA9, 0x2A, 0xFA, 0xD7, 0x91, 0xDD, 0x03, 0x49, 0x06, 0x8C,
+            0x0B, 0xA9, 0xB4, 0xB1, 0xCE, 0x87, 0x98, 0x1E, 0xCA, 0xA5, 0xB6, 0x3E, 0xE6, 0xDA, 0xE7, 0xFE,
+            0xCF, 0x0B, 0xF8, 0xE7, 0x26, 0x58,
+        ];
+        assert_eq!((stream[2] >> 1) & 3, 2);
+        let mut expected = b"the quick brown fox jumps over the lazy dog ".to_vec();
+        for _ in 0..2 {
+            expected.extend(b'a'..=b'z');
+        }
+        assert_eq!(decompress(&stream, expected.len()).unwrap(), expected);
+    }
+
+    #[test]
+    fn corrupt_streams_are_rejected() {
+        let mut compressed = compress(b"some data to compress, some data to compress");
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        assert!(decompress(&compressed, usize::MAX).is_err());
+        assert!(decompress(&compressed[..4], usize::MAX).is_err());
+    }
+}